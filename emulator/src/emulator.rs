@@ -1,3 +1,4 @@
+use sm2m_protocol::emulator::{self, Event};
 use stm32f1xx_hal::gpio;
 
 use crate::{input, output};
@@ -10,37 +11,12 @@ macro_rules! log {
     };
 }
 
-enum Mode {
-    Write,
-    Read,
-}
-
-#[derive(Eq, PartialEq)]
-enum State {
-    Ready,
-    Reset,
-    CheckStatus,
-    Address,
-    Read,
-    ReadData(usize),
-    Write,
-    WriteData(usize),
-    Stop,
-}
-
 pub type LedPin = gpio::Pin<'D', 7, gpio::Output>;
 
 pub struct Machine {
-    input: input::Bus,
-    output: output::Bus,
+    machine: emulator::Machine<input::Bus, output::Bus>,
     led: LedPin,
-    state: State,
-    mode: Mode,
     debug: bool,
-    transfers: usize,
-    last_received: u16,
-    last_address: u16,
-    last_data: u16,
 }
 
 impl Machine {
@@ -52,21 +28,14 @@ impl Machine {
         debug: bool,
     ) -> Self {
         Self {
-            input,
-            output,
+            machine: emulator::Machine::new(input, output, transfers),
             led,
-            state: State::Ready,
-            mode: Mode::Write,
             debug,
-            transfers,
-            last_received: 0,
-            last_address: 0,
-            last_data: 0,
         }
     }
 
     pub fn is_completed(&self) -> bool {
-        self.state == State::Stop
+        self.machine.is_completed()
     }
 
     pub fn set_debug(&mut self, debug: bool) {
@@ -74,146 +43,72 @@ impl Machine {
     }
 
     pub fn set_transfers(&mut self, transfers: usize) {
-        self.transfers = transfers;
+        self.machine.set_transfers(transfers);
     }
 
     pub fn start_write(&mut self, debug: bool) {
         defmt::println!(
             "Start {} bytes write simulation with debug: {}",
-            self.transfers * 2,
+            self.machine.transfers() * 2,
             debug
         );
-        self.mode = Mode::Write;
         self.debug = debug;
-        self.start();
+        self.led.set_high();
+        self.machine.start_write();
     }
 
     pub fn start_read(&mut self, debug: bool) {
         defmt::println!(
             "Start {} bytes read simulation with debug: {}",
-            self.transfers * 2,
+            self.machine.transfers() * 2,
             debug
         );
-        self.mode = Mode::Read;
         self.debug = debug;
-        self.start();
+        self.led.set_high();
+        self.machine.start_read();
     }
 
     pub fn step(&mut self) {
-        match self.state {
-            State::Ready => {
-                self.state = State::Reset;
-                self.output.write(output::Frame::Reset);
-            }
-            State::Reset => {
-                if self.read().is_some() {
-                    self.state = State::CheckStatus;
-                    self.output.write(output::Frame::CheckStatus);
-                }
+        match self.machine.step() {
+            Some(Event::InvalidFrame) => self.handle_invalid_frame(),
+            Some(Event::InvalidData {
+                expected,
+                received,
+                last_received,
+            }) => defmt::println!(
+                "Invalid data read, expected: {}, received: {}, last received {}",
+                expected,
+                received,
+                last_received,
+            ),
+            Some(Event::WriteDone { last_sent }) => {
+                defmt::println!("Done, last sent data {}", last_sent)
             }
-            State::CheckStatus => {
-                if self.read().is_some() {
-                    self.last_address += 1;
-                    self.state = State::Address;
-                    self.output.write(output::Frame::Address(self.last_address));
-                }
+            Some(Event::WriteCompleted) => defmt::println!("Write simulation completed"),
+            Some(Event::ReadCompleted { last_received }) => {
+                defmt::println!("Read simulation completed, last received {}", last_received)
             }
-            State::Address => {
-                if self.read().is_some() {
-                    match self.mode {
-                        Mode::Read => {
-                            self.state = State::Read;
-                            self.output.write(output::Frame::Read);
-                        }
-                        Mode::Write => {
-                            self.state = State::Write;
-                            self.output.write(output::Frame::Write);
-                        }
-                    }
-                }
-            }
-            State::Read => {
-                if self.read().is_some() {
-                    self.state = State::ReadData(1);
-                    self.output.write(output::Frame::ReadData);
-                }
-            }
-            State::ReadData(mut count) => {
-                if let Some(data) = self.read() {
-                    if data == count as u16 - 1 {
-                        self.last_received = data;
-                        if count < self.transfers {
-                            count += 1;
-                            self.state = State::ReadData(count);
-                            self.output.write(output::Frame::ReadData);
-                        } else {
-                            self.state = State::Stop;
-                            self.output.write(output::Frame::Stop);
-                        }
-                    } else {
-                        defmt::println!(
-                            "Invalid data read, expected: {}, received: {}, last received {}",
-                            count,
-                            data,
-                            self.last_received,
-                        );
-                    }
-                }
-            }
-            State::Write => {
-                if self.read().is_some() {
-                    self.state = State::WriteData(1);
-                    self.output.write(output::Frame::WriteData(0));
-                }
-            }
-            State::WriteData(count) => {
-                if self.read().is_some() {
-                    if count < self.transfers {
-                        self.state = State::WriteData(count + 1);
-                        self.output.write(output::Frame::WriteData(count as u16));
-                    } else {
-                        defmt::println!("Done, last sent data {}", count - 1);
-                        self.state = State::Stop;
-                        self.output.write(output::Frame::Stop);
-                    }
-                }
-            }
-            State::Stop => {
-                if self.read().is_some() {
-                    match self.mode {
-                        Mode::Write => defmt::println!("Write simulation completed"),
-                        Mode::Read => defmt::println!(
-                            "Read simulation completed, last received {}",
-                            self.last_received
-                        ),
-                    }
-                }
+            Some(Event::ReadEnded { last_received }) => {
+                defmt::println!("Read ended at end of file, last received {}", last_received)
             }
+            None => {}
         }
     }
 
     pub fn stop(&mut self) {
-        self.state = State::Stop;
-        self.last_address = 0;
-        self.last_received = 0;
-        self.output.write(output::Frame::Stop);
+        self.machine.stop();
     }
 
     pub fn read(&mut self) -> Option<u16> {
-        match self.input.read() {
-            input::Frame::Data(payload) => Some(payload),
-            _ => {
-                log!(self.debug, "Received invalid frame");
-                self.led.set_low();
-                None
-            }
+        let payload = self.machine.read();
+        if payload.is_none() {
+            self.handle_invalid_frame();
         }
+        payload
     }
 
-    fn start(&mut self) {
-        self.led.set_high();
-        self.last_data = 0;
-        self.state = State::Ready;
-        self.step();
+    fn handle_invalid_frame(&mut self) {
+        log!(self.debug, "Received invalid frame");
+        self.led.set_low();
     }
 }
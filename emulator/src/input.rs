@@ -1,14 +1,8 @@
+use sm2m_protocol::emulator::{input::Frame, Input};
 use stm32f1xx_hal::{device, gpio};
 
 pub type Pin<const P: char, const N: u8> = gpio::Pin<P, N, gpio::Input<gpio::PullDown>>;
 
-pub enum Frame {
-    Data(u16),
-    Set,
-    Reset,
-    End,
-}
-
 pub struct Pins {
     pub do_0: Pin<'D', 0>,
     pub do_1: Pin<'D', 1>,
@@ -58,11 +52,6 @@ impl Bus {
         }
     }
 
-    pub fn read(&self) -> Frame {
-        let mask = self.read_mask();
-        Frame::from(mask.reverse_bits())
-    }
-
     fn read_mask(&self) -> DataMask {
         DataMask {
             gpioa: self.gpioa.idr.read().bits(),
@@ -74,6 +63,13 @@ impl Bus {
     }
 }
 
+impl Input for Bus {
+    fn read(&mut self) -> Frame {
+        let mask = self.read_mask();
+        Frame::from(mask.reverse_bits())
+    }
+}
+
 struct DataMask {
     pub gpioa: u32,
     pub gpiob: u32,
@@ -1,3 +1,7 @@
+use sm2m_protocol::{
+    bus::Parity,
+    emulator::{output::Frame, Output},
+};
 use stm32f1xx_hal::{device, gpio};
 
 pub type Pin<const P: char, const N: u8> = gpio::Pin<P, N, gpio::Output<gpio::PushPull>>;
@@ -13,17 +17,6 @@ macro_rules! port_write {
     };
 }
 
-pub enum Frame {
-    Reset,
-    CheckStatus,
-    Address(u16),
-    Write,
-    Read,
-    WriteData(u16),
-    ReadData,
-    Stop,
-}
-
 pub struct Pins {
     pub di_0: Pin<'B', 4>,
     pub di_1: Pin<'B', 5>,
@@ -41,9 +34,9 @@ pub struct Pins {
     pub di_13: Pin<'C', 2>,
     pub di_14: Pin<'C', 3>,
     pub di_15: Pin<'A', 0>,
-    pub ctrli_0: Pin<'A', 3>, // temprarly ignored
-    pub ctrli_1: Pin<'A', 5>, // temprarly ignored
-    pub dtsi: Pin<'A', 6>,    // ignored in favour of DTLI
+    pub ctrli_0: Pin<'A', 3>,
+    pub ctrli_1: Pin<'A', 5>,
+    pub dtsi: Pin<'A', 6>, // ignored in favour of DTLI
     pub dtli: Pin<'A', 7>,
     pub dtei: Pin<'C', 4>,
     pub rsti: Pin<'C', 5>,
@@ -73,13 +66,6 @@ impl Bus {
         bus
     }
 
-    pub fn write(&mut self, frame: Frame) {
-        let mask = DataMask::from(frame);
-        self.pins.dtli.set_high();
-        self.write_mask(mask.reverse_bits());
-        self.pins.dtli.set_low();
-    }
-
     fn write_mask(&mut self, mask: DataMask) {
         port_write!(self.gpioa, 0b1111111100010110, mask.gpioa as u32);
         port_write!(self.gpiob, 0b1111110000001111, mask.gpiob as u32);
@@ -88,6 +74,15 @@ impl Bus {
     }
 }
 
+impl Output for Bus {
+    fn write(&mut self, frame: Frame) {
+        let mask = DataMask::from(frame);
+        self.pins.dtli.set_high();
+        self.write_mask(mask.reverse_bits());
+        self.pins.dtli.set_low();
+    }
+}
+
 #[derive(Default)]
 struct DataMask {
     pub gpioa: u16,
@@ -107,6 +102,40 @@ impl DataMask {
     }
 }
 
+impl From<u16> for DataMask {
+    fn from(payload: u16) -> Self {
+        let mut gpiob = (payload & (1 << 0)) << 4; // set data bit 0 to pb4
+        gpiob |= (payload & (1 << 1)) << 4; // set data bit 1 to pb5
+        gpiob |= (payload & (1 << 2)) << 4; // set data bit 2 to pb6
+        gpiob |= (payload & (1 << 3)) << 4; // set data bit 3 to pb7
+        gpiob |= (payload & (1 << 4)) << 4; // set data bit 4 to pb8
+        gpiob |= (payload & (1 << 5)) << 4; // set data bit 5 to pb9
+
+        let mut gpioc = (payload & (1 << 11)) << 2; // set data bit 11 to pc13
+        gpioc |= (payload & (1 << 12)) >> 12; // set data bit 12 to pc0
+        gpioc |= (payload & (1 << 13)) >> 11; // set data bit 13 to pc2
+        gpioc |= (payload & (1 << 14)) >> 11; // set data bit 14 to pc3
+
+        let mut gpioe = (payload & (1 << 6)) >> 4; // set data bit 6 to pe2
+        gpioe |= (payload & (1 << 7)) >> 4; // set data bit 7 to pe3
+        gpioe |= (payload & (1 << 8)) >> 4; // set data bit 8 to pe4
+        gpioe |= (payload & (1 << 9)) >> 4; // set data bit 9 to pe5
+        gpioe |= (payload & (1 << 10)) >> 4; // set data bit 10 to pe6
+
+        let parity = Parity::of(payload);
+        let mut gpioa = (payload & (1 << 15)) >> 15; // set data bit 15 to pa0
+        gpioa |= (parity.ctrl_0 as u16) << 3; // set CTRLI_0 to pa3
+        gpioa |= (parity.ctrl_1 as u16) << 5; // set CTRLI_1 to pa5
+
+        Self {
+            gpioa,
+            gpiob,
+            gpioc,
+            gpioe,
+        }
+    }
+}
+
 impl From<Frame> for DataMask {
     fn from(value: Frame) -> Self {
         match value {
@@ -116,69 +145,13 @@ impl From<Frame> for DataMask {
                 gpioc: (1 << 5), // set RSTI bit to pc5
                 gpioe: 0,
             },
-            Frame::Address(addr) => {
-                let mut gpioc = (addr & (1 << 1)) << 12; // set address bit 1 to pc13
-                gpioc |= (addr & (1 << 2)) >> 2; // set address bit 2 to pc0
-                gpioc |= (addr & (1 << 3)) >> 1; // set address bit 3 to pc2
-                gpioc |= (addr & (1 << 4)) >> 1; // set address bit 4 to pc3
-
-                Self {
-                    gpioa: (addr & (1 << 6)) >> 6, // set address bit 6 to pa0
-                    gpiob: 0b0011 << 4,            // set command bits [0..4] to 0011 to pb[4..7]
-                    gpioc,
-                    gpioe: (addr & (1 << 0)) << 6, // set address bit 0 to pe6
-                }
-            }
-            Frame::Write => Self {
-                gpioa: 0,
-                gpiob: 1 << 4, // set data bit 0 to 1 to pb4
-                gpioc: 0,
-                gpioe: 0,
-            },
-            Frame::WriteData(payload) => {
-                let mut gpiob = (payload & (1 << 0)) << 4; // set data bit 0 to pb4
-                gpiob |= (payload & (1 << 1)) << 4; // set data bit 1 to pb5
-                gpiob |= (payload & (1 << 2)) << 4; // set data bit 2 to pb6
-                gpiob |= (payload & (1 << 3)) << 4; // set data bit 3 to pb7
-                gpiob |= (payload & (1 << 4)) << 4; // set data bit 4 to pb8
-                gpiob |= (payload & (1 << 5)) << 4; // set data bit 5 to pb9
-
-                let mut gpioc = (payload & (1 << 11)) << 2; // set data bit 11 to pc13
-                gpioc |= (payload & (1 << 12)) >> 12; // set data bit 12 to pc0
-                gpioc |= (payload & (1 << 13)) >> 11; // set data bit 13 to pc2
-                gpioc |= (payload & (1 << 14)) >> 11; // set data bit 14 to pc3
-
-                let mut gpioe = (payload & (1 << 6)) >> 4; // set data bit 6 to pe2
-                gpioe |= (payload & (1 << 7)) >> 4; // set data bit 7 to pe3
-                gpioe |= (payload & (1 << 8)) >> 4; // set data bit 8 to pe4
-                gpioe |= (payload & (1 << 9)) >> 4; // set data bit 9 to pe5
-                gpioe |= (payload & (1 << 10)) >> 4; // set data bit 10 to pe6
-
-                Self {
-                    gpioa: (payload & (1 << 15)) >> 15, // set data bit 15 to pa0
-                    gpiob,
-                    gpioc,
-                    gpioe,
-                }
-            }
-            Frame::Read => Self {
-                gpioa: 0,
-                gpiob: 1 << 5, // set data bit 1 to 1 to pb5
-                gpioc: 0,
-                gpioe: 0,
-            },
-            Frame::CheckStatus | Frame::ReadData => Self {
-                gpioa: 0,
-                gpiob: 0,
-                gpioc: 0,
-                gpioe: 0,
-            },
             Frame::Stop => Self {
                 gpioa: 0,
                 gpiob: 0,
                 gpioc: 1 << 4, // set DTEI bit to pc4
                 gpioe: 0,
             },
+            frame => Self::from(frame.payload()), // commands and data share data lines
         }
     }
 }
@@ -5,7 +5,6 @@ use defmt_rtt as _;
 use panic_probe as _;
 
 mod adapter;
-mod error;
 mod peripherals;
 
 #[rtic::app(device = stm32f1xx_hal::pac, dispatchers = [TAMPER, PVD, CAN_RX1, CAN_SCE])]
@@ -15,17 +14,28 @@ mod app {
 
     use stm32f1xx_hal::{
         gpio::{self, ExtiPin},
+        pac,
         prelude::*,
-        spi,
+        spi, timer,
+    };
+
+    use sm2m_protocol::{
+        adapter::IO_BUFFER_SIZE,
+        blink,
+        worker::{Buffer, Request, Response},
     };
 
     #[shared]
-    struct Shared {}
+    struct Shared {
+        adapter: adapter::Device,
+    }
 
     #[local]
     struct Local {
-        adapter: adapter::Device,
         dtli: gpio::PB13<gpio::Input<gpio::PullDown>>,
+        sdmmc_detect: sdmmc::SdMmcDetectPin,
+        led_timer: timer::CounterHz<pac::TIM2>,
+        worker: adapter::Worker,
     }
 
     macro_rules! into_output {
@@ -34,7 +44,11 @@ mod app {
         };
     }
 
-    #[init]
+    #[init(local = [
+            sdmmc_spi: Option<sdmmc::SdMmcSpi> = None,
+            buffer_a: Buffer = [0; IO_BUFFER_SIZE],
+            buffer_b: Buffer = [0; IO_BUFFER_SIZE],
+        ])]
     fn init(mut cx: init::Context) -> (Shared, Local, init::Monotonics) {
         let mut afio = cx.device.AFIO.constrain();
         let mut flash = cx.device.FLASH.constrain();
@@ -62,12 +76,8 @@ mod app {
             read: into_output!(gpioa.pa2, &mut gpioa.crl),
         };
 
-        let mut indicators = indicators::Indicators::new(pins);
-
-        // Indicate adapter startup
-        indicators.system_error_on();
-        indicators.write_on();
-        indicators.read_on();
+        // LEDs show the boot pattern once the timer starts
+        let indicators = indicators::Indicators::new(pins);
 
         // Disable JTAG
         let (pa15, _pb3, pb4) = afio.mapr.disable_jtag(gpioa.pa15, gpiob.pb3, gpiob.pb4);
@@ -130,7 +140,7 @@ mod app {
         let output = sm2m::output::Bus::new(pins);
 
         // Configure SDMMC
-        let sdmmc_detect_pin = gpioa.pa3.into_pull_up_input(&mut gpioa.crl);
+        let mut sdmmc_detect = gpioa.pa3.into_pull_up_input(&mut gpioa.crl);
         let sdmmc_cs_pin = gpioa.pa4.into_push_pull_output(&mut gpioa.crl);
         let sdmmc_mosi_pin = gpioa.pa7.into_alternate_push_pull(&mut gpioa.crl);
         let sdmmc_sck_pin = gpioa.pa5.into_alternate_push_pull(&mut gpioa.crl);
@@ -143,21 +153,41 @@ mod app {
                 phase: spi::Phase::CaptureOnFirstTransition,
                 polarity: spi::Polarity::IdleLow,
             },
-            25.MHz(),
+            adapter::SPI_CLOCK.kHz(),
             clocks,
         );
+        let sdmmc_spi = sdmmc::SpiBus::new(sdmmc_spi, &clocks);
 
-        let sdmmc_spi = embedded_sdmmc::SdMmcSpi::new(sdmmc_spi, sdmmc_cs_pin);
-        let card = sdmmc::Card::new(sdmmc_spi, sdmmc_detect_pin);
+        // Configure RTC
+        let mut backup_domain = rcc.bkp.constrain(cx.device.BKP, &mut cx.device.PWR);
+        let clock = rtc::Rtc::new(cx.device.RTC, &mut backup_domain);
 
-        // Indicate adapter setup completion
-        cortex_m::delay::Delay::new(cx.core.SYST, 72_000_000).delay_ms(200);
-        indicators.system_error_off();
-        indicators.write_off();
-        indicators.read_off();
+        let sdmmc_spi = embedded_sdmmc::SdMmcSpi::new(sdmmc_spi, sdmmc_cs_pin);
+        let card = sdmmc::Card::new(cx.local.sdmmc_spi.insert(sdmmc_spi), clock);
 
         // Create adapter
-        let adapter = adapter::Device::new(input, output, card, indicators);
+        let buffers = [cx.local.buffer_a, cx.local.buffer_b];
+        let mut adapter = adapter::Device::new(input, output, adapter::Tasks, indicators, buffers);
+        let worker = adapter::Worker::new(card);
+        adapter.configure(sm2m_protocol::Config {
+            parity: cfg!(feature = "parity"),
+            layout: if cfg!(feature = "image") {
+                sm2m_protocol::Layout::Image {
+                    sector_size: adapter::IMAGE_SECTOR_SIZE,
+                }
+            } else {
+                sm2m_protocol::Layout::Files
+            },
+            backups: adapter::BACKUPS,
+            checksums: adapter::CHECKSUMS,
+            byte_order: sm2m_protocol::ByteOrder::Little,
+            log: adapter::LOG,
+            drives: sm2m_protocol::Drives::Single,
+            reserve: adapter::RESERVE,
+            spi_clock: adapter::SPI_CLOCK,
+        });
+        // An inserted card is initialised and its ADAPTER.CFG applied by the storage task
+        adapter.set_attached(sdmmc_detect.is_low());
 
         // Enable SM2M bus interrupt
         let mut dtli = gpiob.pb13.into_pull_down_input(&mut gpiob.crh); // DTLI
@@ -165,7 +195,26 @@ mod app {
         dtli.trigger_on_edge(&mut cx.device.EXTI, gpio::Edge::Falling);
         dtli.enable_interrupt(&mut cx.device.EXTI);
 
-        (Shared {}, Local { adapter, dtli }, init::Monotonics())
+        // Enable LED pattern timer
+        let mut led_timer = cx.device.TIM2.counter_hz(&clocks);
+        led_timer.start((1000 / blink::TICK_MS).Hz()).unwrap();
+        led_timer.listen(timer::Event::Update);
+
+        // Enable SD card hot-plug interrupt
+        sdmmc_detect.make_interrupt_source(&mut afio);
+        sdmmc_detect.trigger_on_edge(&mut cx.device.EXTI, gpio::Edge::RisingFalling);
+        sdmmc_detect.enable_interrupt(&mut cx.device.EXTI);
+
+        (
+            Shared { adapter },
+            Local {
+                dtli,
+                sdmmc_detect,
+                led_timer,
+                worker,
+            },
+            init::Monotonics(),
+        )
     }
 
     #[idle]
@@ -176,12 +225,44 @@ mod app {
         }
     }
 
-    #[task(binds = EXTI15_10, local = [
-            adapter,
-            dtli,
-        ])]
-    fn dtli(cx: dtli::Context) {
-        cx.local.adapter.run();
+    #[task(binds = EXTI15_10, priority = 2, shared = [adapter], local = [dtli])]
+    fn dtli(mut cx: dtli::Context) {
+        cx.shared.adapter.lock(|adapter| adapter.run());
         cx.local.dtli.clear_interrupt_pending_bit();
     }
+
+    /// Tracks card insertion and removal, a bouncing switch at most initialises the card again.
+    #[task(binds = EXTI3, priority = 2, shared = [adapter], local = [sdmmc_detect])]
+    fn sdmmc_detect(mut cx: sdmmc_detect::Context) {
+        let attached = cx.local.sdmmc_detect.is_low();
+        cx.shared
+            .adapter
+            .lock(|adapter| adapter.set_attached(attached));
+        cx.local.sdmmc_detect.clear_interrupt_pending_bit();
+    }
+
+    #[task(binds = TIM2, priority = 2, shared = [adapter], local = [led_timer])]
+    fn led_tick(mut cx: led_tick::Context) {
+        cx.shared
+            .adapter
+            .lock(|adapter| adapter.indicators().tick());
+        cx.local.led_timer.clear_interrupt(timer::Event::Update);
+    }
+
+    /// Runs SD card I/O below the bus priority so the adapter keeps serving the bus from its
+    /// buffers while the card is busy.
+    #[task(priority = 1, capacity = 1, local = [worker])]
+    fn storage(cx: storage::Context, request: Request) {
+        let response = cx.local.worker.process(request);
+        // The response of the single request in flight is taken by `complete` before the adapter
+        // spawns the next request, which fits the task capacity of 1.
+        if complete::spawn(response).is_err() {
+            panic!("storage response completed while another one is pending");
+        }
+    }
+
+    #[task(priority = 2, capacity = 1, shared = [adapter])]
+    fn complete(mut cx: complete::Context, response: Response) {
+        cx.shared.adapter.lock(|adapter| adapter.complete(response));
+    }
 }
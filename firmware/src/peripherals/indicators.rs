@@ -1,3 +1,4 @@
+use sm2m_protocol::blink::Blinker;
 use stm32f1xx_hal::gpio;
 
 pub type Pin<const P: char, const N: u8> = gpio::Pin<P, N, gpio::Output<gpio::PushPull>>;
@@ -8,36 +9,72 @@ pub struct Pins {
     pub read: Pin<'A', 2>,
 }
 
+/// Status LEDs showing the patterns of the blinker, updated by `tick`.
 pub struct Indicators {
     pins: Pins,
+    blinker: Blinker,
 }
 
 impl Indicators {
     pub fn new(pins: Pins) -> Self {
-        Self { pins }
+        Self {
+            pins,
+            blinker: Blinker::new(),
+        }
     }
 
-    pub fn system_error_on(&mut self) {
-        self.pins.system_error.set_low();
+    /// Advances the patterns, called every `blink::TICK_MS`.
+    pub fn tick(&mut self) {
+        let leds = self.blinker.tick();
+        set(&mut self.pins.system_error, leds.system_error);
+        set(&mut self.pins.write, leds.write);
+        set(&mut self.pins.read, leds.read);
+    }
+}
+
+/// LEDs are lit by the low level.
+fn set<const P: char, const N: u8>(pin: &mut Pin<P, N>, lit: bool) {
+    if lit {
+        pin.set_low();
+    } else {
+        pin.set_high();
+    }
+}
+
+impl sm2m_protocol::Indicators for Indicators {
+    fn system_error_on(&mut self) {
+        self.blinker.system_error_on();
+    }
+
+    fn system_error_off(&mut self) {
+        self.blinker.system_error_off();
+    }
+
+    fn write_on(&mut self) {
+        self.blinker.write_on();
+    }
+
+    fn write_off(&mut self) {
+        self.blinker.write_off();
     }
 
-    pub fn system_error_off(&mut self) {
-        self.pins.system_error.set_high();
+    fn read_on(&mut self) {
+        self.blinker.read_on();
     }
 
-    pub fn write_on(&mut self) {
-        self.pins.write.set_low();
+    fn read_off(&mut self) {
+        self.blinker.read_off();
     }
 
-    pub fn write_off(&mut self) {
-        self.pins.write.set_high();
+    fn error(&mut self, opcode: u16) {
+        self.blinker.error(opcode);
     }
 
-    pub fn read_on(&mut self) {
-        self.pins.read.set_low();
+    fn card_missing(&mut self) {
+        self.blinker.card_missing();
     }
 
-    pub fn read_off(&mut self) {
-        self.pins.read.set_high();
+    fn transferred(&mut self, words: usize) {
+        self.blinker.transferred(words);
     }
 }
@@ -1,4 +1,5 @@
 pub mod indicators;
+pub mod rtc;
 pub mod sdmmc;
 pub mod sm2m;
 
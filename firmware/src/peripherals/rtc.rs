@@ -0,0 +1,54 @@
+use stm32f1xx_hal::{backup_domain::BackupDomain, pac, rtc::Rtc as HalRtc};
+
+/// Backup data register which holds `SET_MARK` once the counter was set to the calendar time.
+/// The register is cleared together with the counter when the backup domain loses power.
+const SET_REGISTER: usize = 0;
+const SET_MARK: u16 = 0x5E7C;
+
+/// Real-time clock counting seconds from the LSE crystal, kept running by the backup battery.
+///
+/// The counter and the mark are accessed through the peripheral registers, so every copy of the
+/// clock handed to a card session sees the same time.
+#[derive(Clone)]
+pub struct Rtc;
+
+impl Rtc {
+    /// Starts the counter from the LSE crystal, a counter kept running by the backup battery is
+    /// not reset.
+    pub fn new(rtc: pac::RTC, bkp: &mut BackupDomain) -> Self {
+        HalRtc::new(rtc, bkp);
+        Self
+    }
+}
+
+impl sm2m_storage::Clock for Rtc {
+    fn counter(&self) -> u32 {
+        let rtc = unsafe { &*pac::RTC::ptr() };
+        loop {
+            let high = rtc.cnth.read().bits();
+            let low = rtc.cntl.read().bits();
+            // The low half may have wrapped between the reads
+            if rtc.cnth.read().bits() == high {
+                return high << 16 | low;
+            }
+        }
+    }
+
+    fn is_set(&self) -> bool {
+        let bkp = unsafe { &*pac::BKP::ptr() };
+        bkp.dr[SET_REGISTER].read().d().bits() == SET_MARK
+    }
+
+    fn set(&mut self, seconds: u32) {
+        let rtc = unsafe { &*pac::RTC::ptr() };
+        while rtc.crl.read().rtoff().bit_is_clear() {}
+        rtc.crl.modify(|_, w| w.cnf().set_bit());
+        rtc.cnth.write(|w| unsafe { w.bits(seconds >> 16) });
+        rtc.cntl.write(|w| unsafe { w.bits(seconds & 0xFFFF) });
+        rtc.crl.modify(|_, w| w.cnf().clear_bit());
+        while rtc.crl.read().rtoff().bit_is_clear() {}
+
+        let bkp = unsafe { &*pac::BKP::ptr() };
+        bkp.dr[SET_REGISTER].write(|w| w.d().bits(SET_MARK));
+    }
+}
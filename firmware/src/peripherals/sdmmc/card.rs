@@ -1,47 +1,54 @@
-use stm32f1xx_hal::{gpio, pac, spi};
-
-use crate::error::AppError;
-
-use super::{Controller, StaticTimeSource};
+use embedded_hal::blocking::spi::Transfer;
+use stm32f1xx_hal::{gpio, pac, rcc::Clocks, spi, time::Hertz};
 
 pub type Cs = gpio::PA4<gpio::Output>;
 pub type Sck = gpio::PA5<gpio::Alternate>;
 pub type Miso = gpio::PA6;
 pub type Mosi = gpio::PA7<gpio::Alternate>;
 pub type SpiPins = (Sck, Miso, Mosi);
-pub type SpiBus = spi::Spi<pac::SPI1, spi::Spi1NoRemap, SpiPins, u8>;
+pub type Spi = spi::Spi<pac::SPI1, spi::Spi1NoRemap, SpiPins, u8>;
 pub type SdMmcSpi = embedded_sdmmc::SdMmcSpi<SpiBus, Cs>;
+/// Shorted to ground by the socket switch while a card is inserted.
 pub type SdMmcDetectPin = gpio::PA3<gpio::Input<gpio::PullUp>>;
 
-pub struct Card {
-    spi: SdMmcSpi,
-    _detect_pin: SdMmcDetectPin,
+pub type Card = sm2m_storage::Card<SdMmcSpi, crate::peripherals::rtc::Rtc>;
+
+/// SPI1 bus of the card.
+///
+/// The HAL sets the clock only when the bus is set up, so the clock configured on the card is
+/// switched through the peripheral registers.
+pub struct SpiBus {
+    spi: Spi,
+    /// Clock of the APB2 bus the SPI clock is divided from.
+    pclk: Hertz,
 }
 
-impl Card {
-    pub fn new(spi: SdMmcSpi, detect_pin: SdMmcDetectPin) -> Self {
+impl SpiBus {
+    pub fn new(spi: Spi, clocks: &Clocks) -> Self {
         Self {
             spi,
-            _detect_pin: detect_pin,
+            pclk: clocks.pclk2(),
         }
     }
+}
 
-    pub fn is_attached(&mut self) -> bool {
-        match self.open() {
-            Ok(controller) => {
-                controller.close();
-                true
-            }
-            Err(_) => false,
-        }
+impl Transfer<u8> for SpiBus {
+    type Error = spi::Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u8]) -> Result<&'w [u8], spi::Error> {
+        self.spi.transfer(words)
     }
+}
+
+impl sm2m_storage::SpiClock for SpiBus {
+    fn set_clock(&mut self, khz: u32) {
+        // The clock is divided by a power of 2 from 2 to 256, BR selects 2 << BR
+        let divider = self.pclk.to_kHz().div_ceil(khz.max(1));
+        let br = divider.next_power_of_two().clamp(2, 256).trailing_zeros() - 1;
 
-    pub fn open(&mut self) -> Result<Controller<'_>, AppError> {
-        let spi = self.spi.acquire()?;
-        let time = StaticTimeSource::default();
-        let mut ctl = embedded_sdmmc::Controller::new(spi, time);
-        let vol = ctl.get_volume(embedded_sdmmc::VolumeIdx(0))?;
-        let dir = ctl.open_root_dir(&vol)?;
-        Ok(Controller::new(ctl, vol, dir))
+        let spi = unsafe { &*pac::SPI1::ptr() };
+        while spi.sr.read().bsy().bit_is_set() {}
+        spi.cr1.modify(|_, w| w.spe().clear_bit());
+        spi.cr1.modify(|_, w| w.br().bits(br as u8).spe().set_bit());
     }
 }
@@ -1,7 +1,5 @@
 use heapless::String;
 
-pub type FileName = String<5>;
-
 pub trait AsFileName {
     fn as_file_name(self) -> String<5>;
 }
@@ -1,38 +1,8 @@
+use sm2m_protocol::bus::{input::Action, Input, Parity};
 use stm32f1xx_hal::{device, gpio};
 
 pub type Pin<const P: char, const N: u8> = gpio::Pin<P, N, gpio::Input<gpio::PullDown>>;
 
-pub enum Action {
-    Reset,
-    Stop,
-    Data(u16),
-}
-
-pub enum Frame {
-    CheckStatus,
-    Address(u16),
-    Write,
-    Read,
-    Data(u16),
-}
-
-impl Frame {
-    pub fn from(payload: u16) -> Self {
-        if payload == 0x0000 {
-            Self::CheckStatus
-        } else if payload == 0x0001 {
-            Self::Write
-        } else if payload == 0x0002 {
-            Self::Read
-        } else if payload & 0x0003 == 0x0003 {
-            // Bits 10..15 contains the actual address
-            Self::Address(payload >> 10)
-        } else {
-            Self::Data(payload)
-        }
-    }
-}
-
 pub struct Pins {
     pub di_0: Pin<'B', 7>,
     pub di_1: Pin<'E', 1>,
@@ -76,16 +46,18 @@ impl Bus {
             gpioe: peripherals.GPIOE,
         }
     }
+}
 
-    pub fn read(&self) -> Action {
+impl Input for Bus {
+    fn read(&mut self) -> Action {
         // Read port data
         let pb = self.gpiob.idr.read().bits() as u16;
         let pd = self.gpiod.idr.read().bits() as u16;
         let pe = self.gpioe.idr.read().bits() as u16;
 
         // Read control signals
-        // let ctrli_0 = pd & (1 << 2) == 0; // Read CTRLI_0 from PD2
-        // let ctrli_1 = pb & (1 << 8) == 0; // Read CTRLI_1 from PB8
+        let ctrli_0 = pd & (1 << 2) == 0; // Read CTRLI_0 from PD2
+        let ctrli_1 = pb & (1 << 8) == 0; // Read CTRLI_1 from PB8
         let rsti = pb & (1 << 9) == 0; // Read RSTI from PB9
         let dtei = pb & (1 << 14) == 0; // Read DTEI from PB14
 
@@ -112,7 +84,11 @@ impl Bus {
             payload |= (pd & (1 << 3)) << 11; // Read data bit 14 from PD3
             payload |= (pd & 1) << 15; // Read data bit 15 from PD0
             payload ^= u16::MAX; // Flip bits to convert from logical level 0 to 1
-            Action::Data(payload)
+            let parity = Parity {
+                ctrl_0: ctrli_0,
+                ctrl_1: ctrli_1,
+            };
+            Action::Data(payload, parity)
         }
     }
 }
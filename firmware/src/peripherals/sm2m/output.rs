@@ -1,3 +1,4 @@
+use sm2m_protocol::bus::{output::Frame, Output, Parity};
 use stm32f1xx_hal::{device, gpio};
 
 macro_rules! port_write {
@@ -13,12 +14,6 @@ macro_rules! port_write {
 
 pub type Pin<const P: char, const N: u8> = gpio::Pin<P, N, gpio::Output<gpio::PushPull>>;
 
-pub enum Frame {
-    Ack,
-    Error(u16),
-    Data(u16),
-}
-
 pub struct Pins {
     pub do_0: Pin<'C', 10>,
     pub do_1: Pin<'A', 12>,
@@ -52,6 +47,7 @@ pub struct Bus {
     gpiob: device::GPIOB,
     gpioc: device::GPIOC,
     gpiod: device::GPIOD,
+    parity: bool,
 }
 
 const GPIOA_MASK: u32 = 0b0110000011111111;
@@ -69,33 +65,13 @@ impl Bus {
             gpiob: peripherals.GPIOB,
             gpioc: peripherals.GPIOC,
             gpiod: peripherals.GPIOD,
+            parity: false,
         };
 
         bus.write_ack(); // Set default bus state.
         bus
     }
 
-    pub fn write(&mut self, frame: Frame) {
-        self.pins.rdy.set_high();
-
-        // Assume that all signal pins CTRLO_0, CTRLO_1, RDY, CTRL_D, ERRO, RSTE, SETE, DTEO,
-        // are set to 1 during write.
-        match frame {
-            Frame::Ack => {
-                self.write_ack();
-                self.pins.rdy.set_low();
-            }
-            Frame::Error(opcode) => {
-                self.write_data(opcode);
-                self.pins.erro.set_low();
-            }
-            Frame::Data(data) => {
-                self.write_data(data);
-                self.pins.rdy.set_low();
-            }
-        }
-    }
-
     fn write_ack(&mut self) {
         port_write!(self.gpioa, GPIOA_MASK, u32::MAX); // Write 1 to pin 8, 9, 10, 11, 12, 15
         port_write!(self.gpiob, GPIOB_MASK, u32::MAX); // Write 1 to pin 12, 15
@@ -104,9 +80,16 @@ impl Bus {
     }
 
     fn write_data(&self, data: u16) {
+        let parity = Parity::of(data);
         let data = data as u32 ^ u32::MAX; // Flip bits to convert between logic levels
 
-        let mut pa = 0b1000101000000000; // CTRLO_1 (PA9), CTRLO_0 (PA11) and ERRO (A15) are set to 1
+        let mut pa = 0b1000000000000000; // ERRO (A15) is set to 1
+        if self.parity {
+            pa |= (!parity.ctrl_1 as u32) << 9; // Write CTRLO_1 to PA9
+            pa |= (!parity.ctrl_0 as u32) << 11; // Write CTRLO_0 to PA11
+        } else {
+            pa |= 0b0000101000000000; // CTRLO_1 (PA9) and CTRLO_0 (PA11) are set to 1
+        }
         pa |= (data & (1 << 1)) << 11; // Write data bit 1 to PA12
         pa |= (data & (1 << 2)) << 8; // Write data bit 2 to PA10
         pa |= (data & (1 << 3)) << 5; // Write data bit 3 to PA8
@@ -114,7 +97,8 @@ impl Bus {
         let mut pb = 0b0001000000000000; // RSTE (PB12) is set to 1
         pb |= (data & (1 << 14)) << 1; // Write data bit 14 to PB15
 
-        let mut pc = 0b0001100000001000; // SETE (PC3), DTEO (PC11) and CTRL_D (PC12) are set to 1
+        let mut pc = 0b0000100000001000; // SETE (PC3) and DTEO (PC11) are set to 1
+        pc |= (!self.parity as u32) << 12; // CTRL_D (PC12) is set to 0 to enable parity verification
         pc |= (data & 1) << 10; // Write data bit 0 to PC10
         pc |= (data & (1 << 4)) << 5; // Write data bit 4 to PC9
         pc |= (data & (1 << 5)) << 3; // Write data bit 5 to PC8
@@ -136,3 +120,35 @@ impl Bus {
         port_write!(self.gpiod, GPIOD_MASK, pd);
     }
 }
+
+impl Output for Bus {
+    fn write(&mut self, frame: Frame) {
+        self.pins.rdy.set_high();
+
+        // Assume that all signal pins CTRLO_0, CTRLO_1, RDY, CTRL_D, ERRO, RSTE, SETE, DTEO,
+        // are set to 1 during write.
+        match frame {
+            Frame::Ack => {
+                self.write_ack();
+                self.pins.rdy.set_low();
+            }
+            Frame::Error(opcode) => {
+                self.write_data(opcode);
+                self.pins.erro.set_low();
+            }
+            Frame::Data(data) => {
+                self.write_data(data);
+                self.pins.rdy.set_low();
+            }
+            Frame::End => {
+                self.write_ack();
+                self.pins.dteo.set_low();
+                self.pins.rdy.set_low();
+            }
+        }
+    }
+
+    fn set_parity(&mut self, enabled: bool) {
+        self.parity = enabled;
+    }
+}
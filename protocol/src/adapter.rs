@@ -0,0 +1,998 @@
+use crate::{
+    bus::{input, output, Input, Output, Parity},
+    config::{Config, Layout},
+    error::{AppError, Fault},
+    indicators::Indicators,
+    log::{Event, EventLog},
+    storage::{checksum_name, temp_name, FileName, IMAGE_FILE_NAME, MAX_BACKUPS},
+    worker::{Buffer, Request, Response, Spawn},
+};
+
+enum Mode {
+    Ready,
+    /// Receives the address of Long address.
+    LongAddress,
+    /// Receives the drive number.
+    Drive,
+    /// Receives the address the file of the selected address is moved to, holds it once it
+    /// arrives.
+    Move(Option<u16>),
+    Address,
+    Read,
+    Write,
+    /// Receives the clock time, holds the high word once it arrives.
+    Time(Option<u16>),
+    /// Receives the word offset, holds the high word once it arrives.
+    Seek(Option<u16>),
+    /// Sends the low word of the size, which follows the high word.
+    Size(u16),
+    /// Sends the card capacity, holds the number of words sent.
+    Capacity(usize),
+    /// Sends the error detail, holds the number of words sent.
+    Detail(usize),
+    Error(u16),
+}
+
+/// Data Read mode sends to SM2M.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Source {
+    /// File of the selected address.
+    File,
+    /// Listing of the addresses stored in the drive, with their sizes if `sizes` is set.
+    Addresses { sizes: bool },
+}
+
+/// Kind of the transfer in progress, recorded in the event log once it ends.
+#[derive(Clone, Copy)]
+enum Transfer {
+    Read,
+    Write,
+}
+
+/// Storage request in flight and what to do once it completes.
+///
+/// The values are reported by Error detail as the failing operation, 0 stands for a bus command.
+#[derive(Clone, Copy)]
+enum Job {
+    Status = 1,
+    Remove = 2,
+    Restore = 3,
+    Open = 4,
+    Fetch = 5,
+    Flush = 6,
+    FlushLast = 7,
+    Commit = 8,
+    Finish = 9,
+    SetTime = 10,
+    Attach = 11,
+    Log = 12,
+    Close = 13,
+    Exists = 14,
+    Size = 15,
+    Drive = 16,
+    List = 17,
+    Delete = 18,
+    Move = 19,
+    Capacity = 20,
+    FreeSpace = 21,
+    Reserve = 22,
+    Locate = 23,
+    SpiClock = 24,
+}
+
+/// Number of words the Error detail command answers with.
+pub const ERROR_DETAIL_WORDS: usize = 7;
+
+/// Number of words the Capacity command answers with.
+const CAPACITY_WORDS: usize = 4;
+
+/// Earliest time FAT can store, 1980-01-01 00:00:00.
+const MIN_TIME: u32 = 315_532_800;
+
+pub const IO_BUFFER_SIZE: usize = 1024 * 5; // 5 KB per buffer, both buffers take 10 KB
+
+pub struct Device<I, O, W, L> {
+    input: I,
+    output: O,
+    worker: W,
+    indicators: L,
+    /// Settings the configuration file is applied on top of.
+    defaults: Config,
+    config: Config,
+    mode: Mode,
+    address: u16,
+    file_name: FileName,
+    active: Option<&'static mut Buffer>,
+    spare: Option<&'static mut Buffer>,
+    /// Number of bytes of file data in the active and spare buffer during Read.
+    active_len: usize,
+    spare_len: usize,
+    job: Option<Job>,
+    deferred: Option<input::Action>,
+    buf_pos: usize,
+    file_pos: usize,
+    /// Whether Seek moved the start of the transfer, so Write overwrites the file in place.
+    seek: bool,
+    /// Whether the write session appends to the file instead of replacing it.
+    append: bool,
+    source: Source,
+    transfer: Option<Transfer>,
+    /// Words transferred since the transfer started.
+    words: usize,
+    log: EventLog,
+    /// Context of the last error SM2M reads with the Error detail command.
+    detail: [u16; ERROR_DETAIL_WORDS],
+    /// Size and free space of the card in KB, high words first, sent by the Capacity command.
+    capacity: [u16; CAPACITY_WORDS],
+    /// Card presence reported by the detect pin, unknown until the firmware reports it.
+    attached: Option<bool>,
+    /// Whether the inserted card waits to be initialised until the request in flight completes.
+    attach: bool,
+}
+
+impl<I, O, W, L> Device<I, O, W, L>
+where
+    I: Input,
+    O: Output,
+    W: Spawn,
+    L: Indicators,
+{
+    pub fn new(
+        input: I,
+        output: O,
+        worker: W,
+        indicators: L,
+        buffers: [&'static mut Buffer; 2],
+    ) -> Self {
+        let [active, spare] = buffers;
+        let mut log = EventLog::default();
+        log.record(Event::Boot);
+        Self {
+            input,
+            output,
+            worker,
+            indicators,
+            defaults: Config::default(),
+            config: Config::default(),
+            mode: Mode::Ready,
+            address: 0,
+            file_name: FileName::new(),
+            active: Some(active),
+            spare: Some(spare),
+            active_len: 0,
+            spare_len: 0,
+            job: None,
+            deferred: None,
+            buf_pos: 0,
+            file_pos: 0,
+            seek: false,
+            append: false,
+            source: Source::File,
+            transfer: None,
+            words: 0,
+            log,
+            detail: [0; ERROR_DETAIL_WORDS],
+            capacity: [0; CAPACITY_WORDS],
+            attached: None,
+            attach: false,
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Gives access to the indicators, so the firmware can advance their patterns from a timer.
+    pub fn indicators(&mut self) -> &mut L {
+        &mut self.indicators
+    }
+
+    /// Sets the compiled in settings, which apply until the configuration file of the card is
+    /// read and are restored for a card without one.
+    pub fn configure(&mut self, config: Config) {
+        self.defaults = config;
+        self.apply_config(config);
+    }
+
+    fn apply_config(&mut self, config: Config) {
+        self.config = config;
+        self.output.set_parity(config.parity);
+    }
+
+    pub fn free(self) -> (I, O, W, L) {
+        (self.input, self.output, self.worker, self.indicators)
+    }
+
+    pub fn run(&mut self) {
+        let action = self.input.read();
+        self.execute(action);
+    }
+
+    /// Tracks the card presence reported by the card detect pin.
+    ///
+    /// Once the presence is known check status is answered without talking to the card. Removal
+    /// aborts the transfer in progress. A card reported at boot or inserted afterwards is
+    /// initialised by the worker and its configuration file is applied.
+    pub fn set_attached(&mut self, attached: bool) {
+        let previous = self.attached.replace(attached);
+        if previous == Some(attached) {
+            return;
+        }
+
+        if attached {
+            if !matches!(self.mode, Mode::Error(_)) {
+                self.indicators.system_error_off();
+            }
+            self.attach = true;
+            self.attach();
+        } else {
+            self.attach = false;
+            if matches!(self.mode, Mode::Read | Mode::Write) {
+                // Reported in reply to the next word, like errors of requests nobody waits for
+                self.enter_error(AppError::SdmmcDetached.into(), None);
+                self.indicators.write_off();
+                self.indicators.read_off();
+            } else if !matches!(self.mode, Mode::Error(_)) {
+                self.indicators.card_missing();
+            }
+        }
+    }
+
+    /// Handles the response to the request in flight and replays the action which waited for it.
+    pub fn complete(&mut self, response: Response) {
+        if let Some(buffer) = response.buffer {
+            if self.active.is_none() {
+                self.active = Some(buffer);
+            } else {
+                self.spare = Some(buffer);
+            }
+        }
+
+        if let Some(job) = self.job.take() {
+            match response.result {
+                Ok(size) => self.handle_job(job, size),
+                Err(_) if self.attached == Some(false) => {
+                    self.handle_job_error(job, AppError::SdmmcDetached.into())
+                }
+                Err(fault) => self.handle_job_error(job, fault),
+            }
+        }
+        self.attach();
+
+        if let Some(action) = self.deferred.take() {
+            self.execute(action);
+        }
+    }
+
+    fn execute(&mut self, action: input::Action) {
+        if self.job.is_some() && !self.is_buffered(&action) {
+            self.deferred = Some(action);
+            return;
+        }
+
+        match action {
+            input::Action::Reset => self.handle_reset(),
+            input::Action::Stop => self.handle_stop(),
+            input::Action::Data(payload, parity) => {
+                if self.config.parity && parity != Parity::of(payload) {
+                    // The word is dropped, SM2M may send it again
+                    self.reject_word(AppError::ParityMismatch);
+                } else {
+                    self.handle_data(payload);
+                }
+            }
+        }
+    }
+
+    /// Whether the action can be served from the active buffer while the worker is busy.
+    fn is_buffered(&self, action: &input::Action) -> bool {
+        match (action, &self.mode) {
+            (input::Action::Data(..), Mode::Read | Mode::Write) => self.buf_pos < IO_BUFFER_SIZE,
+            (input::Action::Data(payload, _), Mode::Detail(_) | Mode::Error(_)) => {
+                input::Frame::from(*payload) != input::Frame::AcknowledgeError
+            }
+            _ => false,
+        }
+    }
+
+    fn spawn(&mut self, job: Job, request: Request) {
+        self.job = Some(job);
+        self.worker.spawn(request);
+    }
+
+    fn handle_job(&mut self, job: Job, size: usize) {
+        match job {
+            Job::Status => self.output.write(output::Frame::Ack),
+            Job::Remove => self.enter_write(),
+            Job::Locate => match self.config.layout {
+                Layout::Files => self.remove_stale(),
+                Layout::Image { .. } => self.enter_write(),
+            },
+            Job::Restore => {
+                self.log.record(Event::Restore {
+                    address: self.address,
+                });
+                self.spawn(Job::Finish, Request::Close);
+            }
+            Job::Delete => {
+                self.log.record(Event::Delete {
+                    address: self.address,
+                });
+                self.spawn(Job::Finish, Request::Close);
+            }
+            Job::Move => {
+                if let Mode::Move(Some(to)) = self.mode {
+                    self.log.record(Event::Move {
+                        from: self.address,
+                        to,
+                    });
+                }
+                self.spawn(Job::Finish, Request::Close);
+            }
+            Job::Open | Job::List => {
+                let size = size.min(self.remaining());
+                self.active_len = size;
+                self.file_pos += size;
+                self.mode = Mode::Read;
+                // Only transfers of files are recorded in the event log
+                self.transfer = (self.source == Source::File).then_some(Transfer::Read);
+                self.indicators.read_on();
+                self.output.write(output::Frame::Ack);
+                self.fetch();
+            }
+            Job::Fetch => {
+                let size = size.min(self.remaining());
+                self.spare_len = size;
+                self.file_pos += size;
+            }
+            Job::Flush => {}
+            Job::FlushLast => self.commit(),
+            Job::Commit => self.spawn(Job::Finish, Request::Close),
+            Job::Finish => {
+                self.reset();
+                self.output.write(output::Frame::Ack);
+                self.flush_log();
+            }
+            Job::SetTime | Job::Drive => {
+                self.mode = Mode::Ready;
+                self.output.write(output::Frame::Ack);
+            }
+            Job::Attach => {
+                let mut config = self.defaults;
+                let rejected = match self.spare.as_deref() {
+                    Some(buffer) => config.apply(&buffer[..size]),
+                    None => 0,
+                };
+                self.apply_config(config);
+                self.log.record(Event::Mount { rejected });
+                match config.spi_clock {
+                    0 => self.flush_log(),
+                    khz => self.spawn(Job::SpiClock, Request::SetSpiClock { khz }),
+                }
+            }
+            Job::SpiClock => self.flush_log(),
+            Job::Log => {}
+            Job::Close => self.flush_log(),
+            Job::Exists => {
+                let exists = match self.config.layout {
+                    Layout::Files => size > 0,
+                    Layout::Image { .. } => size > self.start_pos(),
+                };
+                self.mode = Mode::Address;
+                self.output.write(output::Frame::Data(exists as u16));
+            }
+            Job::Capacity => {
+                let kilobytes = size.min(u32::MAX as usize) as u32;
+                self.capacity[0] = (kilobytes >> 16) as u16;
+                self.capacity[1] = kilobytes as u16;
+                self.spawn(Job::FreeSpace, Request::FreeSpace);
+            }
+            Job::FreeSpace => {
+                let kilobytes = size.min(u32::MAX as usize) as u32;
+                self.capacity[2] = (kilobytes >> 16) as u16;
+                self.capacity[3] = kilobytes as u16;
+                self.mode = Mode::Capacity(1);
+                self.output.write(output::Frame::Data(self.capacity[0]));
+            }
+            Job::Reserve if size < self.config.reserve as usize => {
+                self.handle_error(AppError::CardFull)
+            }
+            Job::Reserve => self.start_write(),
+            Job::Size => {
+                let bytes = match self.config.layout {
+                    Layout::Files => size,
+                    Layout::Image { sector_size } => {
+                        size.saturating_sub(self.start_pos()).min(sector_size)
+                    }
+                };
+                let words = bytes.div_ceil(2).min(u32::MAX as usize) as u32;
+                self.mode = Mode::Size(words as u16);
+                self.output.write(output::Frame::Data((words >> 16) as u16));
+            }
+        }
+    }
+
+    fn handle_job_error(&mut self, job: Job, fault: Fault) {
+        match job {
+            // Nobody waits for the reply, the error is reported in reply to the next word.
+            Job::Fetch | Job::Flush => self.enter_error(fault, Some(job)),
+            // The card can't be used, so it is reported as detached until it is inserted again.
+            Job::Attach => {
+                self.attached = Some(false);
+                self.indicators.card_missing();
+            }
+            // The card keeps the clock it was initialised with.
+            Job::SpiClock => self.flush_log(),
+            Job::Log | Job::Close => {}
+            _ => self.report_error(fault, Some(job)),
+        }
+    }
+
+    /// Spawns initialisation of the inserted card once no request is in flight, which is when
+    /// both buffers are back and the spare one can take the configuration file.
+    fn attach(&mut self) {
+        if !self.attach || self.job.is_some() {
+            return;
+        }
+        if let Some(buffer) = self.spare.take() {
+            self.attach = false;
+            self.spawn(Job::Attach, Request::Attach { buffer });
+        }
+    }
+
+    /// Hands the events recorded during the session over to the worker once nothing else is in
+    /// flight, so writing the log never holds up a transfer.
+    fn flush_log(&mut self) {
+        if !self.config.log {
+            self.log.clear();
+            return;
+        }
+        if self.log.is_empty() || self.job.is_some() {
+            return;
+        }
+        if let Some(buffer) = self.spare.take() {
+            let text = self.log.as_bytes();
+            buffer[..text.len()].copy_from_slice(text);
+            let len = text.len();
+            self.log.clear();
+            self.spawn(Job::Log, Request::Log { buffer, len });
+        }
+    }
+
+    /// Records the transfer in progress in the event log.
+    fn end_transfer(&mut self) {
+        let (address, words) = (self.address, self.words);
+        match self.transfer.take() {
+            Some(Transfer::Read) => self.log.record(Event::Read { address, words }),
+            Some(Transfer::Write) => self.log.record(Event::Write { address, words }),
+            None => {}
+        }
+    }
+
+    fn reset(&mut self) {
+        self.buf_pos = 0;
+        self.file_pos = 0;
+        self.mode = Mode::Ready;
+        if self.attached == Some(false) {
+            self.indicators.card_missing();
+        } else {
+            self.indicators.system_error_off();
+        }
+        self.indicators.write_off();
+        self.indicators.read_off();
+    }
+
+    fn handle_reset(&mut self) {
+        // Whatever was left unwritten is discarded by reset, so there is nothing to report.
+        self.end_transfer();
+        self.reset();
+        self.spawn(Job::Close, Request::Close);
+        self.output.write(output::Frame::Ack);
+    }
+
+    fn handle_stop(&mut self) {
+        self.end_transfer();
+        self.indicators.write_off();
+        self.indicators.read_off();
+
+        match self.mode {
+            Mode::Write if self.buf_pos > 0 => self.flush(Job::FlushLast),
+            Mode::Write => self.commit(),
+            _ => self.handle_reset(),
+        }
+    }
+
+    fn handle_data(&mut self, payload: u16) {
+        match self.mode {
+            Mode::Ready => match input::Frame::from(payload) {
+                input::Frame::CheckStatus => self.handle_check_status(),
+                input::Frame::Address(address) => self.handle_address(address),
+                input::Frame::LongAddress => self.handle_long_address(),
+                input::Frame::Drive => self.handle_drive(),
+                input::Frame::List => self.handle_list(false),
+                input::Frame::Capacity => self.spawn(Job::Capacity, Request::Capacity),
+                input::Frame::ListSizes => self.handle_list(true),
+                input::Frame::SetTime => self.handle_set_time(),
+                input::Frame::ErrorDetail => self.handle_error_detail(),
+                input::Frame::AcknowledgeError => self.handle_reset(),
+                _ => self.handle_error(AppError::UnhandledReadyCommand),
+            },
+            Mode::LongAddress => self.handle_address(payload),
+            Mode::Drive => self.handle_drive_payload(payload),
+            Mode::Move(_) => self.handle_move_payload(payload),
+            Mode::Address => match input::Frame::from(payload) {
+                input::Frame::Read => self.handle_read(),
+                input::Frame::Write => self.handle_write(),
+                input::Frame::Append => self.handle_append(),
+                input::Frame::Restore => self.handle_restore(),
+                input::Frame::Delete => self.handle_delete(),
+                input::Frame::Move => self.handle_move(),
+                input::Frame::Seek => self.handle_seek(),
+                input::Frame::Exists => self.handle_exists(),
+                input::Frame::Size => self.handle_size(),
+                _ => self.handle_error(AppError::UnhandledAddressCommand),
+            },
+            Mode::Read => self.handle_read_payload(),
+            Mode::Write => self.handle_write_payload(payload),
+            Mode::Time(high) => self.handle_time_payload(high, payload),
+            Mode::Seek(high) => self.handle_seek_payload(high, payload),
+            Mode::Size(low) => {
+                self.mode = Mode::Address;
+                self.output.write(output::Frame::Data(low));
+            }
+            Mode::Capacity(sent) => {
+                self.mode = if sent + 1 < CAPACITY_WORDS {
+                    Mode::Capacity(sent + 1)
+                } else {
+                    Mode::Ready
+                };
+                self.output.write(output::Frame::Data(self.capacity[sent]));
+            }
+            Mode::Detail(_) | Mode::Error(_)
+                if input::Frame::from(payload) == input::Frame::AcknowledgeError =>
+            {
+                // Ends the session like RSTI, without resetting SM2M
+                self.handle_reset()
+            }
+            Mode::Detail(sent) => self.handle_detail_payload(sent),
+            Mode::Error(_) if input::Frame::from(payload) == input::Frame::ErrorDetail => {
+                self.handle_error_detail()
+            }
+            Mode::Error(opcode) => self.handle_error(Fault::new(opcode)),
+        }
+    }
+
+    fn handle_check_status(&mut self) {
+        match self.attached {
+            Some(true) => self.output.write(output::Frame::Ack),
+            Some(false) => self.handle_error(AppError::SdmmcDetached),
+            None => self.spawn(Job::Status, Request::Status),
+        }
+    }
+
+    fn handle_error_detail(&mut self) {
+        self.mode = Mode::Detail(0);
+        self.output.write(output::Frame::Ack);
+    }
+
+    fn handle_detail_payload(&mut self, sent: usize) {
+        let word = self.detail.get(sent).copied().unwrap_or(0);
+        self.mode = Mode::Detail(sent + 1);
+        self.output.write(output::Frame::Data(word));
+    }
+
+    fn handle_set_time(&mut self) {
+        self.mode = Mode::Time(None);
+        self.output.write(output::Frame::Ack);
+    }
+
+    fn handle_time_payload(&mut self, high: Option<u16>, payload: u16) {
+        match high {
+            None => {
+                self.mode = Mode::Time(Some(payload));
+                self.output.write(output::Frame::Ack);
+            }
+            Some(high) => {
+                let seconds = (high as u32) << 16 | payload as u32;
+                if seconds < MIN_TIME {
+                    self.mode = Mode::Ready;
+                    self.reject_word(AppError::InvalidTime);
+                } else {
+                    self.spawn(Job::SetTime, Request::SetTime { seconds });
+                }
+            }
+        }
+    }
+
+    fn handle_drive(&mut self) {
+        self.mode = Mode::Drive;
+        self.output.write(output::Frame::Ack);
+    }
+
+    fn handle_drive_payload(&mut self, number: u16) {
+        match self.config.drives.drive(number) {
+            Some(drive) => self.spawn(Job::Drive, Request::SelectDrive { drive }),
+            None => {
+                self.mode = Mode::Ready;
+                self.reject_word(AppError::InvalidDrive);
+            }
+        }
+    }
+
+    fn handle_list(&mut self, sizes: bool) {
+        match self.config.layout {
+            Layout::Files => {
+                if let Some(buffer) = self.active.take() {
+                    self.source = Source::Addresses { sizes };
+                    self.words = 0;
+                    self.file_pos = 0;
+                    let request = self.read_request(buffer);
+                    self.spawn(Job::List, request);
+                }
+            }
+            // Every address of the image has a sector, there is nothing to list
+            Layout::Image { .. } => self.handle_error(AppError::UnhandledReadyCommand),
+        }
+    }
+
+    fn handle_long_address(&mut self) {
+        self.mode = Mode::LongAddress;
+        self.output.write(output::Frame::Ack);
+    }
+
+    fn handle_address(&mut self, address: u16) {
+        self.mode = Mode::Address;
+        self.address = address;
+        self.words = 0;
+        self.seek = false;
+        self.append = false;
+        self.source = Source::File;
+        self.file_name = match self.config.layout {
+            Layout::Files => FileName::from(address),
+            Layout::Image { .. } => FileName::from(IMAGE_FILE_NAME),
+        };
+        self.file_pos = self.start_pos();
+        self.output.write(output::Frame::Ack);
+    }
+
+    /// Position of the selected address in its file.
+    fn start_pos(&self) -> usize {
+        match self.config.layout {
+            Layout::Files => 0,
+            Layout::Image { sector_size } => (self.address as usize).saturating_mul(sector_size),
+        }
+    }
+
+    /// End of the data of the selected address in its file, a sector of the drive image ends
+    /// where the next one starts.
+    fn end_pos(&self) -> usize {
+        match (self.config.layout, &self.source) {
+            (Layout::Image { sector_size }, Source::File) => {
+                self.start_pos().saturating_add(sector_size)
+            }
+            _ => usize::MAX,
+        }
+    }
+
+    /// Number of bytes the transfer can move before it reaches the end of the data.
+    fn remaining(&self) -> usize {
+        self.end_pos().saturating_sub(self.file_pos)
+    }
+
+    fn handle_seek(&mut self) {
+        self.mode = Mode::Seek(None);
+        self.output.write(output::Frame::Ack);
+    }
+
+    fn handle_seek_payload(&mut self, high: Option<u16>, payload: u16) {
+        match high {
+            None => self.mode = Mode::Seek(Some(payload)),
+            Some(high) => {
+                let words = (high as usize) << 16 | payload as usize;
+                let pos = self.start_pos().saturating_add(words.saturating_mul(2));
+                self.file_pos = pos.min(self.end_pos());
+                self.seek = true;
+                self.mode = Mode::Address;
+            }
+        }
+        self.output.write(output::Frame::Ack);
+    }
+
+    fn handle_exists(&mut self) {
+        match self.config.layout {
+            Layout::Files => {
+                let name = self.file_name.clone();
+                self.spawn(Job::Exists, Request::Exists { name });
+            }
+            // Whether the image reaches the sector is told by its size
+            Layout::Image { .. } => {
+                let name = self.file_name.clone();
+                self.spawn(Job::Exists, Request::Size { name });
+            }
+        }
+    }
+
+    fn handle_size(&mut self) {
+        let name = self.file_name.clone();
+        self.spawn(Job::Size, Request::Size { name });
+    }
+
+    /// Whether Write overwrites the file in place instead of replacing it.
+    fn in_place(&self) -> bool {
+        self.seek || matches!(self.config.layout, Layout::Image { .. })
+    }
+
+    fn handle_read(&mut self) {
+        if let Some(buffer) = self.active.take() {
+            if self.config.layout == Layout::Files {
+                // Checksums cover whole buffers from the start of the file, so a Read after Seek
+                // reads the buffer which holds the offset and starts sending from the offset
+                self.buf_pos = self.file_pos % IO_BUFFER_SIZE;
+                self.file_pos -= self.buf_pos;
+            }
+            let request = self.read_request(buffer);
+            self.spawn(Job::Open, request);
+        }
+    }
+
+    /// Request which reads the next part of the data Read mode sends into the buffer.
+    fn read_request(&self, buffer: &'static mut Buffer) -> Request {
+        match self.source {
+            Source::File => Request::Read {
+                name: self.file_name.clone(),
+                offset: self.file_pos,
+                buffer,
+                verify: self.config.layout == Layout::Files,
+            },
+            Source::Addresses { sizes } => Request::List {
+                offset: self.file_pos,
+                buffer,
+                sizes,
+            },
+        }
+    }
+
+    fn handle_write(&mut self) {
+        match self.config.layout {
+            Layout::Files => self.check_reserve(),
+            // Sectors are overwritten in place.
+            Layout::Image { .. } => self.start_write(),
+        }
+    }
+
+    fn handle_append(&mut self) {
+        match self.config.layout {
+            Layout::Files => {
+                self.append = true;
+                self.check_reserve();
+            }
+            Layout::Image { .. } => self.handle_error(AppError::UnhandledAddressCommand),
+        }
+    }
+
+    /// Refuses the write session up front when the card has less free space than the reserve,
+    /// instead of running out of space in the middle of it.
+    fn check_reserve(&mut self) {
+        if self.config.reserve > 0 {
+            self.spawn(Job::Reserve, Request::FreeSpace);
+        } else {
+            self.start_write();
+        }
+    }
+
+    /// Prepares the file of the selected address for the write session. A write in place has to
+    /// start within the file, which is checked before any data is accepted, so no acknowledged
+    /// word is lost when the session ends.
+    fn start_write(&mut self) {
+        if !self.append && self.in_place() {
+            let name = self.file_name.clone();
+            let offset = self.file_pos;
+            self.spawn(Job::Locate, Request::Locate { name, offset });
+        } else {
+            self.remove_stale();
+        }
+    }
+
+    /// Removes the file the write session would leave out of date.
+    fn remove_stale(&mut self) {
+        let name = if self.append || self.seek {
+            // Checksums cover blocks counted from the start of the file, appended data doesn't
+            // line up with them and blocks changed in place would no longer match
+            checksum_name(&self.file_name)
+        } else {
+            // Drop the leftover of a session which never ended with Stop
+            temp_name(&self.file_name)
+        };
+        self.spawn(Job::Remove, Request::Remove { name, backups: 0 });
+    }
+
+    fn enter_write(&mut self) {
+        self.mode = Mode::Write;
+        self.transfer = Some(Transfer::Write);
+        self.indicators.write_on();
+        self.output.write(output::Frame::Ack);
+    }
+
+    fn handle_restore(&mut self) {
+        match self.config.layout {
+            Layout::Files => {
+                let name = self.file_name.clone();
+                let backups = self.config.backups.min(MAX_BACKUPS);
+                self.spawn(Job::Restore, Request::Restore { name, backups });
+            }
+            Layout::Image { .. } => self.handle_error(AppError::UnhandledAddressCommand),
+        }
+    }
+
+    fn handle_delete(&mut self) {
+        match self.config.layout {
+            Layout::Files => {
+                let name = self.file_name.clone();
+                let backups = self.config.backups.min(MAX_BACKUPS);
+                self.spawn(Job::Delete, Request::Delete { name, backups });
+            }
+            Layout::Image { .. } => self.handle_error(AppError::UnhandledAddressCommand),
+        }
+    }
+
+    fn handle_move(&mut self) {
+        match self.config.layout {
+            Layout::Files => {
+                self.mode = Mode::Move(None);
+                self.output.write(output::Frame::Ack);
+            }
+            Layout::Image { .. } => self.handle_error(AppError::UnhandledAddressCommand),
+        }
+    }
+
+    fn handle_move_payload(&mut self, to: u16) {
+        self.mode = Mode::Move(Some(to));
+        let request = Request::Move {
+            from: self.file_name.clone(),
+            to: FileName::from(to),
+            backups: self.config.backups.min(MAX_BACKUPS),
+        };
+        self.spawn(Job::Move, request);
+    }
+
+    /// Reads the next part of the file ahead into the spare buffer.
+    fn fetch(&mut self) {
+        if let Some(buffer) = self.spare.take() {
+            let request = self.read_request(buffer);
+            self.spawn(Job::Fetch, request);
+        }
+    }
+
+    fn handle_read_payload(&mut self) {
+        if self.buf_pos >= IO_BUFFER_SIZE {
+            core::mem::swap(&mut self.active, &mut self.spare);
+            core::mem::swap(&mut self.active_len, &mut self.spare_len);
+            self.buf_pos = 0;
+            self.handle_send_buf_chunk();
+            self.fetch();
+        } else {
+            self.handle_send_buf_chunk();
+        }
+    }
+
+    fn handle_send_buf_chunk(&mut self) {
+        if self.buf_pos >= self.active_len {
+            self.output.write(output::Frame::End);
+            return;
+        }
+        if let Some(buf) = self.active.as_deref() {
+            let bytes = [buf[self.buf_pos], buf[self.buf_pos + 1]];
+            let payload = match self.source {
+                Source::File => self.config.byte_order.from_bytes(bytes),
+                Source::Addresses { .. } => u16::from_le_bytes(bytes),
+            };
+            self.output.write(output::Frame::Data(payload));
+            self.buf_pos += 2;
+            self.words += 1;
+            self.indicators.transferred(1);
+        }
+    }
+
+    fn handle_write_payload(&mut self, payload: u16) {
+        if self.buf_pos >= IO_BUFFER_SIZE {
+            self.flush(Job::Flush);
+            self.active = self.spare.take();
+            self.buf_pos = 0;
+        }
+
+        if let Some(buf) = self.active.as_deref_mut() {
+            let bytes = self.config.byte_order.to_bytes(payload);
+            buf[self.buf_pos] = bytes[0];
+            buf[self.buf_pos + 1] = bytes[1];
+            self.buf_pos += 2;
+            self.words += 1;
+            self.indicators.transferred(1);
+            self.output.write(output::Frame::Ack)
+        }
+    }
+
+    /// Writes the active buffer behind to the storage.
+    fn flush(&mut self, job: Job) {
+        if let Some(buffer) = self.active.take() {
+            // Words past the end of the sector are dropped
+            let len = self.buf_pos.min(self.remaining());
+            let request = if self.append {
+                Request::Append {
+                    name: self.file_name.clone(),
+                    buffer,
+                    len,
+                    checksum: false,
+                }
+            } else if self.in_place() {
+                Request::Write {
+                    name: self.file_name.clone(),
+                    offset: self.file_pos,
+                    buffer,
+                    len,
+                }
+            } else {
+                Request::Append {
+                    name: temp_name(&self.file_name),
+                    buffer,
+                    len,
+                    checksum: self.config.checksums,
+                }
+            };
+            self.file_pos += len;
+            self.spawn(job, request);
+        }
+    }
+
+    /// Replaces the file with the data written during the session.
+    fn commit(&mut self) {
+        if self.append || self.in_place() {
+            self.spawn(Job::Finish, Request::Close);
+        } else {
+            let name = self.file_name.clone();
+            let backups = self.config.backups.min(MAX_BACKUPS);
+            self.spawn(Job::Commit, Request::Commit { name, backups });
+        }
+    }
+
+    fn handle_error<T: Into<Fault>>(&mut self, error: T) {
+        self.report_error(error.into(), None);
+    }
+
+    fn report_error(&mut self, fault: Fault, job: Option<Job>) {
+        self.enter_error(fault, job);
+        self.output.write(output::Frame::Error(fault.opcode));
+    }
+
+    /// Reports an error which only rejects the current word and leaves the mode as it is.
+    fn reject_word(&mut self, error: AppError) {
+        let fault = error.into();
+        self.record_error(fault, None);
+        self.output.write(output::Frame::Error(fault.opcode));
+    }
+
+    /// Switches to the error mode which aborts the session, `job` is the request which failed if
+    /// any.
+    fn enter_error(&mut self, fault: Fault, job: Option<Job>) {
+        // The error is repeated for every word until it is cleared, it is recorded once
+        if !matches!(self.mode, Mode::Error(_)) {
+            self.record_error(fault, job);
+        }
+        self.mode = Mode::Error(fault.opcode);
+    }
+
+    fn record_error(&mut self, fault: Fault, job: Option<Job>) {
+        let opcode = fault.opcode;
+        self.log.record(Event::Error { opcode });
+        let offset = self.file_pos as u32;
+        self.detail = [
+            opcode,
+            job.map_or(0, |job| job as u16),
+            self.address,
+            (offset >> 16) as u16,
+            offset as u16,
+            fault.params[0],
+            fault.params[1],
+        ];
+        self.indicators.error(opcode);
+    }
+}
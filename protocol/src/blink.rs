@@ -0,0 +1,209 @@
+use crate::indicators::Indicators;
+
+/// Period the firmware calls `Blinker::tick` with.
+pub const TICK_MS: u32 = 100;
+
+/// Ticks an error code pulse is lit and the whole pulse lasts.
+const PULSE_ON: u32 = 2;
+const PULSE: u32 = 5;
+/// Dark ticks between the digits of an error code and before the code repeats.
+const DIGIT_GAP: u32 = 5;
+const CODE_GAP: u32 = 15;
+
+/// Ticks the missing card blink stays lit and dark.
+const CARD_MISSING_HALF: u32 = 10;
+
+/// Ticks the LEDs run in a circle after boot.
+const BOOT_TICKS: u32 = 12;
+
+/// Words a transfer moves per flicker of its LED.
+const FLICKER_WORDS: usize = 256;
+
+/// Levels of the adapter LEDs, `true` is lit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Leds {
+    pub system_error: bool,
+    pub write: bool,
+    pub read: bool,
+}
+
+/// LED pattern engine driven by a periodic timer.
+///
+/// The adapter switches states through `Indicators` and `tick` turns them into LED levels:
+/// - after boot the LEDs run in a circle for a moment,
+/// - an error blinks its opcode digit by digit on the error LED, zero as ten pulses,
+/// - a missing card slowly blinks the error LED,
+/// - the read and write LEDs are lit during a transfer and flicker faster the faster it goes.
+#[derive(Default)]
+pub struct Blinker {
+    ticks: u32,
+    error: Error,
+    /// Tick the current error pattern started at.
+    since: u32,
+    write: Activity,
+    read: Activity,
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum Error {
+    #[default]
+    None,
+    Lit,
+    Code(u16),
+    CardMissing,
+}
+
+impl Blinker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Advances the patterns by `TICK_MS` and returns the LED levels to show.
+    pub fn tick(&mut self) -> Leds {
+        let tick = self.ticks;
+        self.ticks = self.ticks.wrapping_add(1);
+        let step = tick.wrapping_sub(self.since);
+        let write = self.write.tick();
+        let read = self.read.tick();
+
+        let system_error = match self.error {
+            Error::None if tick < BOOT_TICKS => {
+                let lit = tick % 3;
+                return Leds {
+                    write: lit == 0,
+                    read: lit == 1,
+                    system_error: lit == 2,
+                };
+            }
+            Error::None => false,
+            Error::Lit => true,
+            Error::Code(opcode) => code_level(opcode, step),
+            Error::CardMissing => step % (2 * CARD_MISSING_HALF) < CARD_MISSING_HALF,
+        };
+        Leds {
+            system_error,
+            write,
+            read,
+        }
+    }
+
+    fn show(&mut self, error: Error) {
+        if self.error != error {
+            self.error = error;
+            self.since = self.ticks;
+        }
+    }
+}
+
+impl Indicators for Blinker {
+    fn system_error_on(&mut self) {
+        self.show(Error::Lit);
+    }
+
+    fn system_error_off(&mut self) {
+        self.show(Error::None);
+    }
+
+    fn write_on(&mut self) {
+        self.write.start();
+    }
+
+    fn write_off(&mut self) {
+        self.write.stop();
+    }
+
+    fn read_on(&mut self) {
+        self.read.start();
+    }
+
+    fn read_off(&mut self) {
+        self.read.stop();
+    }
+
+    fn error(&mut self, opcode: u16) {
+        self.show(Error::Code(opcode));
+    }
+
+    fn card_missing(&mut self) {
+        self.show(Error::CardMissing);
+    }
+
+    fn transferred(&mut self, words: usize) {
+        self.write.count(words);
+        self.read.count(words);
+    }
+}
+
+/// Level of the error LED `step` ticks into the repeating blink code of `opcode`.
+fn code_level(opcode: u16, step: u32) -> bool {
+    let mut digits = [0; 5];
+    let mut count = 0;
+    let mut value = opcode;
+    loop {
+        digits[count] = (value % 10) as u32;
+        count += 1;
+        value /= 10;
+        if value == 0 {
+            break;
+        }
+    }
+
+    let pulses = |digit: u32| if digit == 0 { 10 } else { digit };
+    let length: u32 = digits[..count]
+        .iter()
+        .map(|&digit| pulses(digit) * PULSE + DIGIT_GAP)
+        .sum::<u32>()
+        + CODE_GAP;
+
+    let mut step = step % length;
+    for &digit in digits[..count].iter().rev() {
+        let length = pulses(digit) * PULSE;
+        if step < length {
+            return step % PULSE < PULSE_ON;
+        }
+        if step < length + DIGIT_GAP {
+            return false;
+        }
+        step -= length + DIGIT_GAP;
+    }
+    false
+}
+
+/// Read or write LED which is lit during a transfer and goes dark for a tick every
+/// `FLICKER_WORDS` words, at most every other tick.
+#[derive(Default)]
+struct Activity {
+    active: bool,
+    dark: bool,
+    words: usize,
+}
+
+impl Activity {
+    fn start(&mut self) {
+        self.active = true;
+    }
+
+    fn stop(&mut self) {
+        self.active = false;
+        self.dark = false;
+        self.words = 0;
+    }
+
+    fn count(&mut self, words: usize) {
+        if self.active {
+            self.words = self.words.saturating_add(words);
+        }
+    }
+
+    fn tick(&mut self) -> bool {
+        if self.dark {
+            self.dark = false;
+        } else if self.words >= FLICKER_WORDS {
+            // Words beyond the flicker rate are dropped, so the LED stops flickering with the
+            // transfer instead of catching up afterwards
+            self.words = (self.words - FLICKER_WORDS).min(FLICKER_WORDS);
+            self.dark = true;
+        }
+        self.active && !self.dark
+    }
+}
@@ -0,0 +1,35 @@
+pub mod input;
+pub mod output;
+
+/// SM2M input bus (DI_0..DI_15 and input control lines) as seen by the adapter.
+pub trait Input {
+    fn read(&mut self) -> input::Action;
+}
+
+/// SM2M output bus (DO_0..DO_15 and output control lines) as driven by the adapter.
+pub trait Output {
+    fn write(&mut self, frame: output::Frame);
+    /// Drives CTRLO_0, CTRLO_1 with the parity of each data word and CTRL_D low when enabled,
+    /// otherwise leaves all of them high so SM2M does not verify control bits.
+    fn set_parity(&mut self, enabled: bool);
+}
+
+/// Control bits which accompany every data word on CTRLI_x and CTRLO_x lines.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Parity {
+    /// Control bit for the high byte, bits 8..15.
+    pub ctrl_0: bool,
+    /// Control bit for the low byte, bits 0..7.
+    pub ctrl_1: bool,
+}
+
+impl Parity {
+    /// Control bits for the word, each of them is set when its byte has an even number of ones.
+    pub fn of(word: u16) -> Self {
+        let [high, low] = word.to_be_bytes();
+        Self {
+            ctrl_0: high.count_ones() % 2 == 0,
+            ctrl_1: low.count_ones() % 2 == 0,
+        }
+    }
+}
@@ -0,0 +1,121 @@
+use super::Parity;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Reset,
+    Stop,
+    Data(u16, Parity),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frame {
+    CheckStatus,
+    /// Short form of address selection, carries addresses 0..63 in bits 10..15.
+    Address(u16),
+    /// Selects the address carried in the following data word, the full 16-bit range.
+    LongAddress,
+    /// Selects the logical drive carried in the following data word.
+    Drive,
+    Write,
+    Read,
+    /// Sets the storage clock to seconds since 1970-01-01 00:00:00 local time carried in the
+    /// following two data words, high word first.
+    SetTime,
+    /// Replaces the file at the address with its most recent backup.
+    Restore,
+    /// Answers the following data words with the context of the last error.
+    ErrorDetail,
+    /// Clears the error and returns to Ready mode.
+    AcknowledgeError,
+    /// Followed by the word offset transfers of the selected address start from.
+    Seek,
+    /// Appends the following words to the file of the selected address.
+    Append,
+    /// Asks whether the selected address has data.
+    Exists,
+    /// Asks for the number of words stored at the selected address.
+    Size,
+    /// Asks for the addresses stored in the drive.
+    List,
+    /// Asks for the addresses stored in the drive, each followed by its number of words.
+    ListSizes,
+    /// Removes the file of the selected address.
+    Delete,
+    /// Moves the file of the selected address to the address carried in the following data word.
+    Move,
+    /// Asks for the card capacity and free space in KB, two words each.
+    Capacity,
+    Data(u16),
+}
+
+impl Frame {
+    pub fn from(payload: u16) -> Self {
+        if payload == 0x0000 {
+            Self::CheckStatus
+        } else if payload == 0x0001 {
+            Self::Write
+        } else if payload == 0x0002 {
+            Self::Read
+        } else if payload == 0x0004 {
+            Self::SetTime
+        } else if payload == 0x0005 {
+            Self::Restore
+        } else if payload == 0x0006 {
+            Self::ErrorDetail
+        } else if payload == 0x0008 {
+            Self::AcknowledgeError
+        } else if payload == 0x0009 {
+            Self::Seek
+        } else if payload == 0x000A {
+            Self::Append
+        } else if payload == 0x000C {
+            Self::Exists
+        } else if payload == 0x000D {
+            Self::Size
+        } else if payload == 0x000E {
+            Self::LongAddress
+        } else if payload == 0x0010 {
+            Self::Drive
+        } else if payload == 0x0011 {
+            Self::List
+        } else if payload == 0x0012 {
+            Self::ListSizes
+        } else if payload == 0x0014 {
+            Self::Delete
+        } else if payload == 0x0015 {
+            Self::Move
+        } else if payload == 0x0016 {
+            Self::Capacity
+        } else if payload & 0x0003 == 0x0003 {
+            // Bits 10..15 contains the actual address
+            Self::Address(payload >> 10)
+        } else {
+            Self::Data(payload)
+        }
+    }
+
+    pub fn payload(&self) -> u16 {
+        match self {
+            Self::CheckStatus => 0x0000,
+            Self::Write => 0x0001,
+            Self::Read => 0x0002,
+            Self::SetTime => 0x0004,
+            Self::Restore => 0x0005,
+            Self::ErrorDetail => 0x0006,
+            Self::AcknowledgeError => 0x0008,
+            Self::Seek => 0x0009,
+            Self::Append => 0x000A,
+            Self::Exists => 0x000C,
+            Self::Size => 0x000D,
+            Self::LongAddress => 0x000E,
+            Self::Drive => 0x0010,
+            Self::List => 0x0011,
+            Self::ListSizes => 0x0012,
+            Self::Delete => 0x0014,
+            Self::Move => 0x0015,
+            Self::Capacity => 0x0016,
+            Self::Address(address) => (address << 10) | 0x0003,
+            Self::Data(payload) => *payload,
+        }
+    }
+}
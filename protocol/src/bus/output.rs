@@ -0,0 +1,8 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frame {
+    Ack,
+    Error(u16),
+    Data(u16),
+    /// End of data on DTEO, answers a Read word past the end of the file.
+    End,
+}
@@ -0,0 +1,228 @@
+use crate::storage::{Drive, MAX_BACKUPS};
+
+/// File in the card root which overrides the compiled in settings.
+pub const CONFIG_FILE_NAME: &str = "ADAPTER.CFG";
+
+/// Drive image sector size used when the configuration file selects the image layout without
+/// giving a sector size.
+pub const DEFAULT_SECTOR_SIZE: usize = 64 * 1024;
+
+/// Largest drive image sector size, the sector of the highest address has to start within the
+/// 4 GB a FAT file can hold.
+pub const MAX_SECTOR_SIZE: usize = (u32::MAX / u16::MAX as u32) as usize;
+
+/// Range of the SPI clock in kHz the card may be driven with, from the slowest clock cards have
+/// to answer at up to the fastest one of the default speed mode.
+pub const SPI_CLOCK_RANGE: core::ops::RangeInclusive<u32> = 100..=25_000;
+
+/// Adapter settings which may differ between installations.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Config {
+    /// Verify CTRLI_x parity of incoming words and drive CTRLO_x parity of outgoing words.
+    pub parity: bool,
+    /// How SM2M addresses map onto the storage.
+    pub layout: Layout,
+    /// Number of previous file versions kept when a file is written, up to `MAX_BACKUPS`. Drive
+    /// images are overwritten in place and never backed up.
+    pub backups: u8,
+    /// Store a checksum of every written buffer next to the file, so corrupted data is reported
+    /// when it is read back. Files with checksums are verified regardless of this setting.
+    pub checksums: bool,
+    /// Order in which the bytes of a word are stored.
+    pub byte_order: ByteOrder,
+    /// Record boot, card mounts, transfers and errors in the event log on the card.
+    pub log: bool,
+    /// How the drive numbers SM2M selects map onto the card.
+    pub drives: Drives,
+    /// Free space in KB the card has to keep, Write and Append are refused once less is left.
+    /// 0 disables the check, which costs a scan of the allocation table before every session.
+    pub reserve: u32,
+    /// SPI clock in kHz the card is driven with once it is initialised, 0 keeps the clock the
+    /// storage was set up with.
+    pub spi_clock: u32,
+}
+
+/// Storage layout of the data SM2M transfers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// Every address is stored in its own file named after the address, writes replace the file.
+    #[default]
+    Files,
+    /// All addresses share a single drive image where every address selects a sector of
+    /// `sector_size` bytes, reads and writes happen in place.
+    Image { sector_size: usize },
+}
+
+/// Mapping of the logical drives SM2M selects onto the card.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Drives {
+    /// A single drive in the root of the first partition, only drive 0 can be selected.
+    #[default]
+    Single,
+    /// Drive `n` is the `DRIVE<n>` directory in the root of the first partition.
+    Directories,
+    /// Drive `n` is the root of partition `n`.
+    Partitions,
+}
+
+impl Drives {
+    /// Location of the drive `number`, `None` if there is no such drive.
+    pub fn drive(self, number: u16) -> Option<Drive> {
+        match self {
+            Drives::Single => (number == 0).then(Drive::default),
+            Drives::Directories => Some(Drive {
+                partition: 0,
+                directory: Some(u8::try_from(number).ok()?),
+            }),
+            Drives::Partitions => (number < MAX_PARTITIONS).then_some(Drive {
+                partition: number as u8,
+                directory: None,
+            }),
+        }
+    }
+}
+
+/// Number of partitions in the partition table.
+const MAX_PARTITIONS: u16 = 4;
+
+/// Byte order of the words stored on the storage.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ByteOrder {
+    #[default]
+    Little,
+    Big,
+}
+
+impl ByteOrder {
+    pub fn to_bytes(self, word: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::Little => word.to_le_bytes(),
+            ByteOrder::Big => word.to_be_bytes(),
+        }
+    }
+
+    pub fn from_bytes(self, bytes: [u8; 2]) -> u16 {
+        match self {
+            ByteOrder::Little => u16::from_le_bytes(bytes),
+            ByteOrder::Big => u16::from_be_bytes(bytes),
+        }
+    }
+}
+
+impl Config {
+    /// Applies `key = value` lines of the configuration file on top of the settings and returns
+    /// the number of lines which were rejected.
+    ///
+    /// Keys are case insensitive, empty lines and lines starting with `#` are skipped. A line with
+    /// an unknown key or an invalid value is rejected and leaves the setting untouched.
+    pub fn apply(&mut self, text: &[u8]) -> usize {
+        let mut rejected = 0;
+        let mut sector_size = None;
+        for line in text.split(|&byte| byte == b'\n') {
+            let line = line.trim_ascii();
+            if line.is_empty() || line.starts_with(b"#") {
+                continue;
+            }
+
+            let setting = core::str::from_utf8(line)
+                .ok()
+                .and_then(|line| line.split_once('='))
+                .map(|(key, value)| (key.trim(), value.trim()));
+            let applied = setting.and_then(|(key, value)| self.set(key, value, &mut sector_size));
+            if applied.is_none() {
+                rejected += 1;
+            }
+        }
+
+        if let (Layout::Image { .. }, Some(sector_size)) = (self.layout, sector_size) {
+            self.layout = Layout::Image { sector_size };
+        }
+        rejected
+    }
+
+    fn set(&mut self, key: &str, value: &str, sector_size: &mut Option<usize>) -> Option<()> {
+        let is = |name: &str| key.eq_ignore_ascii_case(name);
+        if is("parity") {
+            self.parity = switch(value)?;
+        } else if is("layout") {
+            self.layout = layout(value, self.layout)?;
+        } else if is("sector_size") {
+            // Applied once the layout is known, whichever line comes first
+            let size: usize = value.parse().ok()?;
+            if size == 0 || !size.is_multiple_of(2) || size > MAX_SECTOR_SIZE {
+                return None;
+            }
+            *sector_size = Some(size);
+        } else if is("backups") {
+            self.backups = value
+                .parse()
+                .ok()
+                .filter(|&backups| backups <= MAX_BACKUPS)?;
+        } else if is("checksums") {
+            self.checksums = switch(value)?;
+        } else if is("byte_order") {
+            self.byte_order = byte_order(value)?;
+        } else if is("log") {
+            self.log = switch(value)?;
+        } else if is("drives") {
+            self.drives = drives(value)?;
+        } else if is("reserve") {
+            self.reserve = value.parse().ok()?;
+        } else if is("spi_clock") {
+            self.spi_clock = value
+                .parse()
+                .ok()
+                .filter(|clock| SPI_CLOCK_RANGE.contains(clock))?;
+        } else {
+            return None;
+        }
+        Some(())
+    }
+}
+
+fn switch(value: &str) -> Option<bool> {
+    if value.eq_ignore_ascii_case("on") {
+        Some(true)
+    } else if value.eq_ignore_ascii_case("off") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+fn layout(value: &str, current: Layout) -> Option<Layout> {
+    if value.eq_ignore_ascii_case("files") {
+        Some(Layout::Files)
+    } else if value.eq_ignore_ascii_case("image") {
+        match current {
+            Layout::Image { .. } => Some(current),
+            Layout::Files => Some(Layout::Image {
+                sector_size: DEFAULT_SECTOR_SIZE,
+            }),
+        }
+    } else {
+        None
+    }
+}
+
+fn byte_order(value: &str) -> Option<ByteOrder> {
+    if value.eq_ignore_ascii_case("little") {
+        Some(ByteOrder::Little)
+    } else if value.eq_ignore_ascii_case("big") {
+        Some(ByteOrder::Big)
+    } else {
+        None
+    }
+}
+
+fn drives(value: &str) -> Option<Drives> {
+    if value.eq_ignore_ascii_case("single") {
+        Some(Drives::Single)
+    } else if value.eq_ignore_ascii_case("directories") {
+        Some(Drives::Directories)
+    } else if value.eq_ignore_ascii_case("partitions") {
+        Some(Drives::Partitions)
+    } else {
+        None
+    }
+}
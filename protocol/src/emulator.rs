@@ -0,0 +1,221 @@
+pub mod input;
+pub mod output;
+
+/// Adapter output bus as seen by SM2M.
+pub trait Input {
+    fn read(&mut self) -> input::Frame;
+}
+
+/// Adapter input bus as driven by SM2M.
+pub trait Output {
+    fn write(&mut self, frame: output::Frame);
+}
+
+/// Notable outcome of a single emulator step.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    InvalidFrame,
+    InvalidData {
+        expected: u16,
+        received: u16,
+        last_received: u16,
+    },
+    WriteDone {
+        last_sent: u16,
+    },
+    WriteCompleted,
+    ReadCompleted {
+        last_received: u16,
+    },
+    /// Adapter signalled the end of the file on DTEO before all words were read.
+    ReadEnded {
+        last_received: u16,
+    },
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum Mode {
+    Write,
+    Read,
+}
+
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum State {
+    Ready,
+    Reset,
+    CheckStatus,
+    Address,
+    Read,
+    ReadData(usize),
+    Write,
+    WriteData(usize),
+    Stop,
+}
+
+/// SM2M side of the conversation.
+///
+/// Writes `transfers` words with values `0..transfers` to the adapter or reads them back and
+/// verifies the sequence. Every step is driven by the adapter confirming the previous frame.
+pub struct Machine<I, O> {
+    input: I,
+    output: O,
+    state: State,
+    mode: Mode,
+    transfers: usize,
+    last_received: u16,
+    last_address: u16,
+}
+
+impl<I, O> Machine<I, O>
+where
+    I: Input,
+    O: Output,
+{
+    pub fn new(input: I, output: O, transfers: usize) -> Self {
+        Self {
+            input,
+            output,
+            state: State::Ready,
+            mode: Mode::Write,
+            transfers,
+            last_received: 0,
+            last_address: 0,
+        }
+    }
+
+    pub fn is_completed(&self) -> bool {
+        self.state == State::Stop
+    }
+
+    pub fn transfers(&self) -> usize {
+        self.transfers
+    }
+
+    pub fn set_transfers(&mut self, transfers: usize) {
+        self.transfers = transfers;
+    }
+
+    pub fn start_write(&mut self) {
+        self.mode = Mode::Write;
+        self.start();
+    }
+
+    pub fn start_read(&mut self) {
+        self.mode = Mode::Read;
+        self.start();
+    }
+
+    pub fn step(&mut self) -> Option<Event> {
+        if self.state == State::Ready {
+            self.state = State::Reset;
+            self.output.write(output::Frame::Reset);
+            return None;
+        }
+
+        let data = match self.input.read() {
+            input::Frame::Data(payload) => payload,
+            input::Frame::End if matches!(self.state, State::ReadData(_)) => {
+                return Some(Event::ReadEnded {
+                    last_received: self.last_received,
+                });
+            }
+            _ => return Some(Event::InvalidFrame),
+        };
+
+        match self.state {
+            State::Ready => None,
+            State::Reset => {
+                self.state = State::CheckStatus;
+                self.output.write(output::Frame::CheckStatus);
+                None
+            }
+            State::CheckStatus => {
+                self.last_address += 1;
+                self.state = State::Address;
+                self.output.write(output::Frame::Address(self.last_address));
+                None
+            }
+            State::Address => {
+                match self.mode {
+                    Mode::Read => {
+                        self.state = State::Read;
+                        self.output.write(output::Frame::Read);
+                    }
+                    Mode::Write => {
+                        self.state = State::Write;
+                        self.output.write(output::Frame::Write);
+                    }
+                }
+                None
+            }
+            State::Read => {
+                self.state = State::ReadData(1);
+                self.output.write(output::Frame::ReadData);
+                None
+            }
+            State::ReadData(count) => {
+                let expected = count as u16 - 1;
+                if data == expected {
+                    self.last_received = data;
+                    if count < self.transfers {
+                        self.state = State::ReadData(count + 1);
+                        self.output.write(output::Frame::ReadData);
+                    } else {
+                        self.state = State::Stop;
+                        self.output.write(output::Frame::Stop);
+                    }
+                    None
+                } else {
+                    Some(Event::InvalidData {
+                        expected,
+                        received: data,
+                        last_received: self.last_received,
+                    })
+                }
+            }
+            State::Write => {
+                self.state = State::WriteData(1);
+                self.output.write(output::Frame::WriteData(0));
+                None
+            }
+            State::WriteData(count) => {
+                if count < self.transfers {
+                    self.state = State::WriteData(count + 1);
+                    self.output.write(output::Frame::WriteData(count as u16));
+                    None
+                } else {
+                    self.state = State::Stop;
+                    self.output.write(output::Frame::Stop);
+                    Some(Event::WriteDone {
+                        last_sent: count as u16 - 1,
+                    })
+                }
+            }
+            State::Stop => match self.mode {
+                Mode::Write => Some(Event::WriteCompleted),
+                Mode::Read => Some(Event::ReadCompleted {
+                    last_received: self.last_received,
+                }),
+            },
+        }
+    }
+
+    pub fn stop(&mut self) {
+        self.state = State::Stop;
+        self.last_address = 0;
+        self.last_received = 0;
+        self.output.write(output::Frame::Stop);
+    }
+
+    pub fn read(&mut self) -> Option<u16> {
+        match self.input.read() {
+            input::Frame::Data(payload) => Some(payload),
+            _ => None,
+        }
+    }
+
+    fn start(&mut self) {
+        self.state = State::Ready;
+        self.step();
+    }
+}
@@ -0,0 +1,8 @@
+/// Frame SM2M reads from the adapter output bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frame {
+    Data(u16),
+    Set,
+    Reset,
+    End,
+}
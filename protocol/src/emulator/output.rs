@@ -0,0 +1,28 @@
+use crate::bus::input;
+
+/// Frame SM2M writes to the adapter input bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Frame {
+    Reset,
+    CheckStatus,
+    Address(u16),
+    Write,
+    Read,
+    WriteData(u16),
+    ReadData,
+    Stop,
+}
+
+impl Frame {
+    /// Returns the word set on DI_0..DI_15 lines.
+    pub fn payload(&self) -> u16 {
+        match self {
+            Self::Reset | Self::Stop => 0,
+            Self::CheckStatus | Self::ReadData => input::Frame::CheckStatus.payload(),
+            Self::Address(address) => input::Frame::Address(*address).payload(),
+            Self::Write => input::Frame::Write.payload(),
+            Self::Read => input::Frame::Read.payload(),
+            Self::WriteData(payload) => *payload,
+        }
+    }
+}
@@ -0,0 +1,67 @@
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppError {
+    SdmmcDetached,
+    UnhandledReadyCommand,
+    UnhandledAddressCommand,
+    ParityMismatch,
+    InvalidTime,
+    BackupNotFound,
+    ChecksumMismatch,
+    InvalidDrive,
+    AddressNotFound,
+    CardFull,
+    OffsetPastEnd,
+}
+
+impl AppError {
+    pub fn opcode(&self) -> u16 {
+        use AppError::*;
+
+        match self {
+            SdmmcDetached => 1,
+            UnhandledReadyCommand => 2,
+            UnhandledAddressCommand => 3,
+            ParityMismatch => 45,
+            InvalidTime => 46,
+            BackupNotFound => 47,
+            ChecksumMismatch => 48,
+            InvalidDrive => 49,
+            AddressNotFound => 50,
+            CardFull => 51,
+            OffsetPastEnd => 52,
+        }
+    }
+}
+
+impl From<AppError> for u16 {
+    fn from(value: AppError) -> Self {
+        value.opcode()
+    }
+}
+
+/// Error reported to SM2M together with the parameters of its cause, e.g. the command of a
+/// command timeout or both checksums of a CRC error.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Fault {
+    pub opcode: u16,
+    pub params: [u16; 2],
+    /// The cause is transient, like a timeout or a corrupted transfer, so the request may succeed
+    /// when it is repeated.
+    pub retryable: bool,
+}
+
+impl Fault {
+    pub fn new(opcode: u16) -> Self {
+        Self {
+            opcode,
+            params: [0; 2],
+            retryable: false,
+        }
+    }
+}
+
+impl From<AppError> for Fault {
+    fn from(value: AppError) -> Self {
+        Self::new(value.opcode())
+    }
+}
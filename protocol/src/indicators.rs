@@ -0,0 +1,28 @@
+/// Adapter status LEDs.
+///
+/// Implementations which can only switch the LEDs keep the defaults, which show errors and a
+/// missing card by lighting the error LED.
+pub trait Indicators {
+    fn system_error_on(&mut self);
+    fn system_error_off(&mut self);
+    fn write_on(&mut self);
+    fn write_off(&mut self);
+    fn read_on(&mut self);
+    fn read_off(&mut self);
+
+    /// Shows the error opcode until `system_error_off`.
+    fn error(&mut self, opcode: u16) {
+        let _ = opcode;
+        self.system_error_on();
+    }
+
+    /// Shows that no usable card is inserted until `system_error_off`.
+    fn card_missing(&mut self) {
+        self.system_error_on();
+    }
+
+    /// Counts words transferred by the read or write session in progress.
+    fn transferred(&mut self, words: usize) {
+        let _ = words;
+    }
+}
@@ -0,0 +1,22 @@
+#![no_std]
+
+pub mod adapter;
+pub mod blink;
+pub mod bus;
+pub mod checksum;
+pub mod config;
+pub mod emulator;
+pub mod error;
+pub mod indicators;
+pub mod log;
+pub mod storage;
+pub mod time;
+pub mod virtual_bus;
+pub mod worker;
+
+pub use adapter::Device;
+pub use config::{ByteOrder, Config, Drives, Layout};
+pub use error::AppError;
+pub use indicators::Indicators;
+pub use storage::Storage;
+pub use worker::Worker;
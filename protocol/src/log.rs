@@ -0,0 +1,120 @@
+use core::fmt::{self, Write};
+
+use heapless::String;
+
+use crate::time::DateTime;
+
+/// File the event log is appended to.
+pub const LOG_FILE_NAME: &str = "ADAPTER.LOG";
+
+/// File the event log is moved to once it grows past `MAX_LOG_SIZE`, replacing the previous one.
+pub const OLD_LOG_FILE_NAME: &str = "ADAPTER.OLD";
+
+/// Size the event log file may grow to before it is rotated.
+pub const MAX_LOG_SIZE: usize = 64 * 1024;
+
+/// Size of the events kept in RAM between flushes, events which don't fit are dropped.
+pub const LOG_BUFFER_SIZE: usize = 512;
+
+/// Longest line an event is formatted to.
+const MAX_LINE_LEN: usize = 48;
+
+/// Length of the time every line starts with once it is written, `YYYY-MM-DD HH:MM:SS `.
+const STAMP_LEN: usize = 20;
+
+/// Something worth recording in the event log.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Event {
+    Boot,
+    /// Card was mounted, `rejected` lines of its configuration file were skipped.
+    Mount {
+        rejected: usize,
+    },
+    Read {
+        address: u16,
+        words: usize,
+    },
+    Write {
+        address: u16,
+        words: usize,
+    },
+    Restore {
+        address: u16,
+    },
+    Delete {
+        address: u16,
+    },
+    Move {
+        from: u16,
+        to: u16,
+    },
+    Error {
+        opcode: u16,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Event::Boot => write!(f, "boot"),
+            Event::Mount { rejected } => write!(f, "mount rejected={rejected}"),
+            Event::Read { address, words } => write!(f, "read address={address} words={words}"),
+            Event::Write { address, words } => write!(f, "write address={address} words={words}"),
+            Event::Restore { address } => write!(f, "restore address={address}"),
+            Event::Delete { address } => write!(f, "delete address={address}"),
+            Event::Move { from, to } => write!(f, "move address={from} to={to}"),
+            Event::Error { opcode } => write!(f, "error opcode={opcode}"),
+        }
+    }
+}
+
+/// Events recorded since the last flush, one line per event.
+#[derive(Default)]
+pub struct EventLog {
+    text: String<LOG_BUFFER_SIZE>,
+}
+
+impl EventLog {
+    pub fn record(&mut self, event: Event) {
+        let mut line = String::<MAX_LINE_LEN>::new();
+        if writeln!(line, "{event}").is_ok() {
+            // A partial line would garble the next one, so the whole event is dropped
+            self.text.push_str(&line).ok();
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        self.text.as_bytes()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+    }
+}
+
+/// Starts every line of the first `len` bytes of `buf` with the time and returns the length of
+/// the stamped text. Lines which don't fit into `buf` anymore are dropped.
+pub fn stamp(buf: &mut [u8], len: usize, time: DateTime) -> usize {
+    let mut prefix = String::<STAMP_LEN>::new();
+    write!(prefix, "{time} ").ok();
+
+    // The lines are moved to the end of the buffer to make room for the time in front of each
+    let start = buf.len() - len;
+    buf.copy_within(..len, start);
+    let (text, lines) = buf.split_at_mut(start);
+    let mut size = 0;
+    for line in lines.split_inclusive(|&byte| byte == b'\n') {
+        let end = size + prefix.len() + line.len();
+        if end > text.len() {
+            break;
+        }
+        text[size..size + prefix.len()].copy_from_slice(prefix.as_bytes());
+        text[size + prefix.len()..end].copy_from_slice(line);
+        size = end;
+    }
+    size
+}
@@ -0,0 +1,139 @@
+use heapless::String;
+
+use crate::error::Fault;
+
+/// Short 8.3 file name.
+pub type FileName = String<12>;
+
+/// Drive image file used when every address selects a sector instead of a file.
+pub const IMAGE_FILE_NAME: &str = "DRIVE0.IMG";
+
+/// Extension of the file a write session goes to until it ends with Stop.
+pub const TEMP_EXTENSION: &str = "TMP";
+
+/// Name of the temporary file the file is written to.
+pub fn temp_name(name: &str) -> FileName {
+    let mut temp = FileName::from(name);
+    temp.push('.').ok();
+    temp.push_str(TEMP_EXTENSION).ok();
+    temp
+}
+
+/// Name of the file which holds block checksums of the file, `<base>.CRC` for the file itself,
+/// `<base>.CRT` for its temporary file and `<base>.CR1`..`<base>.CR9` for its backups.
+pub fn checksum_name(name: &str) -> FileName {
+    let (base, extension) = name.split_once('.').unwrap_or((name, ""));
+    let suffix = match extension {
+        TEMP_EXTENSION => 'T',
+        _ => extension
+            .chars()
+            .last()
+            .filter(char::is_ascii_digit)
+            .unwrap_or('C'),
+    };
+    let mut checksums = FileName::from(base);
+    checksums.push_str(".CR").ok();
+    checksums.push(suffix).ok();
+    checksums
+}
+
+/// Address held by the file in the files layout, `None` for other files like backups.
+pub fn address_of(name: &str) -> Option<u16> {
+    let address = name.parse().ok()?;
+    (FileName::from(address) == name).then_some(address)
+}
+
+/// Part of the card a logical drive selected by SM2M is stored in.
+///
+/// The default is the root of the first partition, which also holds the adapter files like the
+/// configuration file and the event log.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Drive {
+    /// Index of the partition in the partition table.
+    pub partition: u8,
+    /// Number of the `DRIVE<n>` directory in the partition root, `None` for the root itself.
+    pub directory: Option<u8>,
+}
+
+/// Name of the directory which holds logical drive `number`.
+pub fn drive_dir_name(number: u8) -> FileName {
+    let mut name = FileName::from("DRIVE");
+    name.push_str(&FileName::from(number)).ok();
+    name
+}
+
+/// Maximum number of previous file versions kept as backups.
+pub const MAX_BACKUPS: u8 = 9;
+
+/// Name of the backup `generation` of the file, 1 is the most recent one.
+pub fn backup_name(name: &str, generation: u8) -> FileName {
+    let mut backup = FileName::from(name);
+    backup.push_str(".BK").ok();
+    backup.push((b'0' + generation) as char).ok();
+    backup
+}
+
+/// File storage the adapter reads from and writes to.
+///
+/// Every error returned by the storage is reported to SM2M as its opcode, the parameters of the
+/// fault can be read back with the Error detail command.
+pub trait Storage {
+    type Error: Into<Fault>;
+
+    fn is_attached(&mut self) -> bool;
+
+    /// Returns the file size or `None` if the file does not exist.
+    fn file_size(&mut self, name: &str) -> Result<Option<usize>, Self::Error>;
+
+    /// Removes the file if it exists.
+    fn remove_file(&mut self, name: &str) -> Result<(), Self::Error>;
+
+    /// Renames the file replacing `to` if it exists, returns `false` if there is no file to rename.
+    fn rename_file(&mut self, from: &str, to: &str) -> Result<bool, Self::Error>;
+
+    /// Reads the file starting from `offset` into `buf` and returns the number of bytes read, none
+    /// when `offset` is past the end of the file.
+    fn read_file(
+        &mut self,
+        name: &str,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, Self::Error>;
+
+    /// Calls `visit` with the name and size of every file in the drive.
+    fn list_files(&mut self, visit: &mut dyn FnMut(&str, usize)) -> Result<(), Self::Error>;
+
+    /// Returns the size of the volume the drive is stored in, in bytes.
+    fn total_space(&mut self) -> Result<u64, Self::Error>;
+
+    /// Returns the space left for files on the volume the drive is stored in, in bytes.
+    fn free_space(&mut self) -> Result<u64, Self::Error>;
+
+    /// Appends `buf` to the end of the file creating it if necessary.
+    fn append_file(&mut self, name: &str, buf: &[u8]) -> Result<usize, Self::Error>;
+
+    /// Overwrites the existing file content starting from `offset` with `buf` in place, extending
+    /// the file if `buf` goes past its end, and returns the number of bytes written.
+    fn write_file(&mut self, name: &str, offset: usize, buf: &[u8]) -> Result<usize, Self::Error>;
+
+    /// Sets the clock the file timestamps are taken from to `seconds` since 1970-01-01 00:00:00
+    /// local time.
+    fn set_time(&mut self, seconds: u32) -> Result<(), Self::Error>;
+
+    /// Returns the time files are stamped with in seconds since 1970-01-01 00:00:00 local time.
+    fn now(&self) -> u32;
+
+    /// Drives the card with an SPI clock of `khz`, closing the open session.
+    fn set_spi_clock(&mut self, khz: u32) -> Result<(), Self::Error>;
+
+    /// Prepares a newly inserted card. The session of the removed card is dropped without writing
+    /// to the new one.
+    fn attach(&mut self) -> Result<(), Self::Error>;
+
+    /// Makes the following operations use files of the drive, closing the session of the
+    /// previous one.
+    fn set_drive(&mut self, drive: Drive) -> Result<(), Self::Error>;
+
+    /// Releases the volume and the file kept open by previous calls once the transfer ends.
+    fn close(&mut self) -> Result<(), Self::Error>;
+}
@@ -0,0 +1,279 @@
+use core::cell::{Cell, RefCell};
+
+use crate::{
+    bus::{self, input, output, Parity},
+    emulator::{self, Event, Machine},
+    worker::{Request, Spawn, Worker},
+    Device, Indicators, Storage,
+};
+
+/// Signal levels of SM2M bus lines, `true` stands for high level.
+///
+/// All lines are active low: a logical 1 on a data line and an asserted control signal are both
+/// represented by low level, exactly as the adapter and the emulator drive their GPIO ports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Lines {
+    pub di: u16,
+    pub ctrli_0: bool,
+    pub ctrli_1: bool,
+    pub rsti: bool,
+    pub dtli: bool,
+    pub dtei: bool,
+    pub do_: u16,
+    pub ctrlo_0: bool,
+    pub ctrlo_1: bool,
+    pub rdy: bool,
+    pub ctrl_d: bool,
+    pub erro: bool,
+    pub rste: bool,
+    pub sete: bool,
+    pub dteo: bool,
+}
+
+impl Default for Lines {
+    fn default() -> Self {
+        Self {
+            di: u16::MAX,
+            ctrli_0: true,
+            ctrli_1: true,
+            rsti: true,
+            dtli: true,
+            dtei: true,
+            do_: u16::MAX,
+            ctrlo_0: true,
+            ctrlo_1: true,
+            rdy: true,
+            ctrl_d: true,
+            erro: true,
+            rste: true,
+            sete: true,
+            dteo: true,
+        }
+    }
+}
+
+/// Host side model of the wires between SM2M and the adapter.
+///
+/// Both sides change line levels one signal at a time and the bus latches falling edges on
+/// DTLI, RDY and ERRO, which are the interrupt sources on real hardware. Storage requests of the
+/// adapter are kept until the bus runs the worker, the same way the storage task is pended.
+#[derive(Default)]
+pub struct VirtualBus {
+    lines: RefCell<Lines>,
+    dtli: Cell<bool>,
+    rdy: Cell<bool>,
+    erro: Cell<bool>,
+    parity: Cell<bool>,
+    request: Cell<Option<Request>>,
+}
+
+impl VirtualBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn lines(&self) -> Lines {
+        *self.lines.borrow()
+    }
+
+    /// Adapter end of the bus.
+    pub fn adapter(&self) -> AdapterPort<'_> {
+        AdapterPort(self)
+    }
+
+    /// SM2M end of the bus.
+    pub fn emulator(&self) -> EmulatorPort<'_> {
+        EmulatorPort(self)
+    }
+
+    /// Storage task of the adapter.
+    pub fn storage(&self) -> StoragePort<'_> {
+        StoragePort(self)
+    }
+
+    /// Delivers pending interrupts to both sides until the bus settles.
+    ///
+    /// DTLI falling edge runs the adapter, a pending storage request runs the worker, RDY
+    /// falling edge steps the emulator and ERRO falling edge makes the emulator read the error
+    /// opcode. Returns the last signal the emulator received, which is the outcome of the session
+    /// since the conversation stops right after it.
+    pub fn run<S, L>(
+        &self,
+        adapter: &mut Device<AdapterPort<'_>, AdapterPort<'_>, StoragePort<'_>, L>,
+        worker: &mut Worker<S>,
+        emulator: &mut Machine<EmulatorPort<'_>, EmulatorPort<'_>>,
+    ) -> Option<Signal>
+    where
+        S: Storage,
+        L: Indicators,
+    {
+        let mut signal = None;
+        loop {
+            if self.dtli.take() {
+                adapter.run();
+            } else if self.process(adapter, worker) {
+                continue;
+            } else if self.rdy.take() {
+                if let Some(event) = emulator.step() {
+                    signal = Some(Signal::Event(event));
+                }
+            } else if self.erro.take() {
+                signal = match emulator.read() {
+                    Some(opcode) => Some(Signal::Error(opcode)),
+                    None => Some(Signal::Event(Event::InvalidFrame)),
+                };
+            } else {
+                return signal;
+            }
+        }
+    }
+
+    /// Runs the worker on the pending storage request, returns `false` when there is none.
+    pub fn process<S, L>(
+        &self,
+        adapter: &mut Device<AdapterPort<'_>, AdapterPort<'_>, StoragePort<'_>, L>,
+        worker: &mut Worker<S>,
+    ) -> bool
+    where
+        S: Storage,
+        L: Indicators,
+    {
+        match self.request.take() {
+            Some(request) => {
+                adapter.complete(worker.process(request));
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn drive<F: FnOnce(&mut Lines)>(&self, f: F) {
+        let mut lines = self.lines.borrow_mut();
+        let before = *lines;
+        f(&mut lines);
+        latch(&self.dtli, before.dtli, lines.dtli);
+        latch(&self.rdy, before.rdy, lines.rdy);
+        latch(&self.erro, before.erro, lines.erro);
+    }
+}
+
+fn latch(edge: &Cell<bool>, before: bool, after: bool) {
+    if before && !after {
+        edge.set(true);
+    }
+}
+
+/// Outcome of running the bus.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Signal {
+    Event(Event),
+    Error(u16),
+}
+
+#[derive(Clone, Copy)]
+pub struct AdapterPort<'a>(&'a VirtualBus);
+
+impl<'a> bus::Input for AdapterPort<'a> {
+    fn read(&mut self) -> input::Action {
+        let lines = self.0.lines();
+        if !lines.rsti {
+            input::Action::Reset
+        } else if !lines.dtei {
+            input::Action::Stop
+        } else {
+            let parity = Parity {
+                ctrl_0: !lines.ctrli_0,
+                ctrl_1: !lines.ctrli_1,
+            };
+            input::Action::Data(!lines.di, parity)
+        }
+    }
+}
+
+impl<'a> bus::Output for AdapterPort<'a> {
+    fn write(&mut self, frame: output::Frame) {
+        self.0.drive(|lines| lines.rdy = true);
+
+        let parity = self.0.parity.get();
+        match frame {
+            output::Frame::Ack => {
+                self.0.drive(|lines| write_data(lines, 0, None));
+                self.0.drive(|lines| lines.rdy = false);
+            }
+            output::Frame::Error(opcode) => {
+                self.0
+                    .drive(|lines| write_data(lines, opcode, parity.then(|| Parity::of(opcode))));
+                self.0.drive(|lines| lines.erro = false);
+            }
+            output::Frame::Data(data) => {
+                self.0
+                    .drive(|lines| write_data(lines, data, parity.then(|| Parity::of(data))));
+                self.0.drive(|lines| lines.rdy = false);
+            }
+            output::Frame::End => {
+                self.0.drive(|lines| write_data(lines, 0, None));
+                self.0.drive(|lines| lines.dteo = false);
+                self.0.drive(|lines| lines.rdy = false);
+            }
+        }
+    }
+
+    fn set_parity(&mut self, enabled: bool) {
+        self.0.parity.set(enabled);
+    }
+}
+
+fn write_data(lines: &mut Lines, data: u16, parity: Option<Parity>) {
+    lines.do_ = !data;
+    lines.ctrlo_0 = !parity.is_some_and(|parity| parity.ctrl_0);
+    lines.ctrlo_1 = !parity.is_some_and(|parity| parity.ctrl_1);
+    lines.rdy = true;
+    lines.ctrl_d = parity.is_none();
+    lines.erro = true;
+    lines.rste = true;
+    lines.sete = true;
+    lines.dteo = true;
+}
+
+#[derive(Clone, Copy)]
+pub struct EmulatorPort<'a>(&'a VirtualBus);
+
+impl<'a> emulator::Input for EmulatorPort<'a> {
+    fn read(&mut self) -> emulator::input::Frame {
+        let lines = self.0.lines();
+        if !lines.rste {
+            emulator::input::Frame::Reset
+        } else if !lines.sete {
+            emulator::input::Frame::Set
+        } else if !lines.dteo {
+            emulator::input::Frame::End
+        } else {
+            emulator::input::Frame::Data(!lines.do_)
+        }
+    }
+}
+
+impl<'a> emulator::Output for EmulatorPort<'a> {
+    fn write(&mut self, frame: emulator::output::Frame) {
+        self.0.drive(|lines| lines.dtli = true);
+        let payload = frame.payload();
+        let parity = Parity::of(payload);
+        self.0.drive(|lines| {
+            lines.di = !payload;
+            lines.ctrli_0 = !parity.ctrl_0;
+            lines.ctrli_1 = !parity.ctrl_1;
+            lines.rsti = frame != emulator::output::Frame::Reset;
+            lines.dtei = frame != emulator::output::Frame::Stop;
+        });
+        self.0.drive(|lines| lines.dtli = false);
+    }
+}
+
+#[derive(Clone, Copy)]
+pub struct StoragePort<'a>(&'a VirtualBus);
+
+impl<'a> Spawn for StoragePort<'a> {
+    fn spawn(&mut self, request: Request) {
+        self.0.request.set(Some(request));
+    }
+}
@@ -0,0 +1,508 @@
+use crate::{
+    adapter::IO_BUFFER_SIZE,
+    checksum::crc32,
+    config::CONFIG_FILE_NAME,
+    error::{AppError, Fault},
+    log::{stamp, LOG_FILE_NAME, MAX_LOG_SIZE, OLD_LOG_FILE_NAME},
+    storage::{address_of, backup_name, checksum_name, temp_name, Drive, FileName, Storage},
+    time::DateTime,
+};
+
+/// Size of a block checksum stored in the checksum file.
+const CHECKSUM_SIZE: usize = 4;
+
+/// Number of times a request which failed with a retryable fault is tried before the fault is
+/// reported.
+pub const MAX_ATTEMPTS: usize = 3;
+
+/// Buffer of bytes passed between the adapter and the storage worker.
+pub type Buffer = [u8; IO_BUFFER_SIZE];
+
+/// Storage operation the adapter hands over to the worker.
+pub enum Request {
+    /// Checks whether the storage is attached.
+    Status,
+    /// Removes the file if it exists, keeping it as the most recent of `backups` generations.
+    Remove { name: FileName, backups: u8 },
+    /// Replaces the file with its temporary file once the write session ends, keeping the file as
+    /// the most recent of `backups` generations.
+    Commit { name: FileName, backups: u8 },
+    /// Replaces the file with its most recent backup and moves older generations down.
+    Restore { name: FileName, backups: u8 },
+    /// Removes the file if it exists, keeping it as the most recent of `backups` generations.
+    /// Unlike `Remove` the backups are left alone when there is no file.
+    Delete { name: FileName, backups: u8 },
+    /// Renames the file, the file it replaces is kept as the most recent of `backups`
+    /// generations. Fails with `AppError::AddressNotFound` if there is no file to move.
+    Move {
+        from: FileName,
+        to: FileName,
+        backups: u8,
+    },
+    /// Fills the buffer with the file content starting from `offset`, the rest of the buffer is
+    /// filled with zeros. Verifies the data against the block checksum if the file has one and
+    /// `verify` is set, `offset` has to be at the start of a block then.
+    Read {
+        name: FileName,
+        offset: usize,
+        buffer: &'static mut Buffer,
+        verify: bool,
+    },
+    /// Appends the first `len` bytes of the buffer to the file, and their checksum to the
+    /// checksum file if `checksum` is set.
+    Append {
+        name: FileName,
+        buffer: &'static mut Buffer,
+        len: usize,
+        checksum: bool,
+    },
+    /// Overwrites the file content starting from `offset` with the first `len` bytes of the buffer.
+    Write {
+        name: FileName,
+        offset: usize,
+        buffer: &'static mut Buffer,
+        len: usize,
+    },
+    /// Fills the buffer with the listing of the addresses stored in the drive starting from byte
+    /// `offset` of the listing, the rest of the buffer is filled with zeros. Every address is
+    /// followed by the high and the low word of its number of words if `sizes` is set, the words
+    /// are stored in little endian order.
+    List {
+        offset: usize,
+        buffer: &'static mut Buffer,
+        sizes: bool,
+    },
+    /// Returns 1 if the file exists, 0 otherwise.
+    Exists { name: FileName },
+    /// Returns the file size, 0 if the file doesn't exist.
+    Size { name: FileName },
+    /// Returns the file size if the file exists and reaches `offset`, so it can be written in
+    /// place from there. Fails with `AppError::AddressNotFound` or `AppError::OffsetPastEnd`.
+    Locate { name: FileName, offset: usize },
+    /// Returns the size of the volume in KB.
+    Capacity,
+    /// Returns the free space of the volume in KB.
+    FreeSpace,
+    /// Sets the storage clock.
+    SetTime { seconds: u32 },
+    /// Sets the SPI clock of the card in kHz.
+    SetSpiClock { khz: u32 },
+    /// Makes the following requests use files of the drive. The configuration file and the event
+    /// log always stay in the default drive.
+    SelectDrive { drive: Drive },
+    /// Initialises a newly inserted card and reads its configuration file into the buffer, the
+    /// rest of the buffer is filled with zeros.
+    Attach { buffer: &'static mut Buffer },
+    /// Appends the first `len` bytes of the buffer to the event log with every line stamped with
+    /// the storage time, moving the log aside once it grows too large.
+    Log {
+        buffer: &'static mut Buffer,
+        len: usize,
+    },
+    /// Closes the storage session.
+    Close,
+}
+
+/// Outcome of a request, gives the buffer back to the adapter.
+pub struct Response {
+    pub buffer: Option<&'static mut Buffer>,
+    /// Number of bytes transferred or the error.
+    pub result: Result<usize, Fault>,
+}
+
+/// Hands requests over to the worker.
+///
+/// The adapter keeps at most one request in flight and expects `Device::complete` to be called
+/// with its response, which is how the firmware runs storage I/O in a lower priority task.
+pub trait Spawn {
+    fn spawn(&mut self, request: Request);
+}
+
+/// Executes storage requests on behalf of the adapter.
+///
+/// Requests which can be repeated without changing their outcome (`Read`, `List`, `Exists`,
+/// `Size`, `Locate`, `Capacity`, `FreeSpace`, `SetTime` and `Attach`) are tried again with a new
+/// session when they fail with a retryable fault. The other requests may have been partly carried out, so
+/// their faults are reported right away.
+pub struct Worker<S> {
+    storage: S,
+    /// Drive selected by the adapter.
+    drive: Drive,
+}
+
+impl<S: Storage> Worker<S> {
+    pub fn new(storage: S) -> Self {
+        Self {
+            storage,
+            drive: Drive::default(),
+        }
+    }
+
+    pub fn free(self) -> S {
+        self.storage
+    }
+
+    pub fn process(&mut self, request: Request) -> Response {
+        let (buffer, result) = match request {
+            Request::Status => {
+                let result = if self.storage.is_attached() {
+                    Ok(0)
+                } else {
+                    Err(AppError::SdmmcDetached.into())
+                };
+                (None, result)
+            }
+            Request::Remove { name, backups } => (None, self.remove(&name, backups).map(|_| 0)),
+            Request::Commit { name, backups } => (None, self.commit(&name, backups).map(|_| 0)),
+            Request::Restore { name, backups } => (None, self.restore(&name, backups).map(|_| 0)),
+            Request::Delete { name, backups } => {
+                (None, self.remove_existing(&name, backups).map(|_| 0))
+            }
+            Request::Move { from, to, backups } => {
+                (None, self.move_file(&from, &to, backups).map(|_| 0))
+            }
+            Request::Read {
+                name,
+                offset,
+                buffer,
+                verify,
+            } => {
+                let result = self.retry(|worker| worker.read(&name, offset, buffer, verify));
+                (Some(buffer), result)
+            }
+            Request::Append {
+                name,
+                buffer,
+                len,
+                checksum,
+            } => {
+                let result = self.append(&name, &buffer[..len], checksum);
+                (Some(buffer), result)
+            }
+            Request::Write {
+                name,
+                offset,
+                buffer,
+                len,
+            } => {
+                let result = self.storage.write_file(&name, offset, &buffer[..len]);
+                (Some(buffer), result.map_err(Into::into))
+            }
+            Request::List {
+                offset,
+                buffer,
+                sizes,
+            } => {
+                let result = self.retry(|worker| worker.list(offset, buffer, sizes));
+                (Some(buffer), result)
+            }
+            Request::Exists { name } => {
+                let result = self.retry(|worker| {
+                    let size = worker.storage.file_size(&name).map_err(Into::into)?;
+                    Ok(size.is_some() as usize)
+                });
+                (None, result)
+            }
+            Request::Size { name } => {
+                let result = self.retry(|worker| {
+                    let size = worker.storage.file_size(&name).map_err(Into::into)?;
+                    Ok(size.unwrap_or(0))
+                });
+                (None, result)
+            }
+            Request::Locate { name, offset } => {
+                let result = self.retry(|worker| {
+                    match worker.storage.file_size(&name).map_err(Into::into)? {
+                        Some(size) if offset <= size => Ok(size),
+                        Some(_) => Err(AppError::OffsetPastEnd.into()),
+                        None => Err(AppError::AddressNotFound.into()),
+                    }
+                });
+                (None, result)
+            }
+            Request::Capacity => {
+                let result = self.retry(|worker| {
+                    let bytes = worker.storage.total_space().map_err(Into::into)?;
+                    Ok(kilobytes(bytes))
+                });
+                (None, result)
+            }
+            Request::FreeSpace => {
+                let result = self.retry(|worker| {
+                    let bytes = worker.storage.free_space().map_err(Into::into)?;
+                    Ok(kilobytes(bytes))
+                });
+                (None, result)
+            }
+            Request::SetTime { seconds } => {
+                let result = self.retry(|worker| {
+                    let result = worker.storage.set_time(seconds).map(|_| 0);
+                    result.map_err(Into::into)
+                });
+                (None, result)
+            }
+            Request::SetSpiClock { khz } => {
+                let result = self.storage.set_spi_clock(khz).map(|_| 0);
+                (None, result.map_err(Into::into))
+            }
+            Request::SelectDrive { drive } => {
+                self.drive = drive;
+                let result = self.storage.set_drive(drive).map(|_| 0);
+                (None, result.map_err(Into::into))
+            }
+            Request::Attach { buffer } => {
+                let result = self.retry(|worker| worker.attach(buffer));
+                (Some(buffer), result)
+            }
+            Request::Log { buffer, len } => {
+                let result = self.in_default_drive(|worker| worker.log(buffer, len));
+                (Some(buffer), result)
+            }
+            Request::Close => (None, self.storage.close().map(|_| 0).map_err(Into::into)),
+        };
+
+        if result.is_err() {
+            let _ = self.storage.close(); // the original error is reported
+        }
+
+        Response { buffer, result }
+    }
+
+    /// Runs the operation until it succeeds, fails with a fault which is not retryable or runs out
+    /// of `MAX_ATTEMPTS`.
+    fn retry<T>(
+        &mut self,
+        mut operation: impl FnMut(&mut Self) -> Result<T, Fault>,
+    ) -> Result<T, Fault> {
+        let mut attempt = 1;
+        loop {
+            match operation(self) {
+                Err(fault) if fault.retryable && attempt < MAX_ATTEMPTS => {
+                    // The failed session may be out of step with the card, the next attempt
+                    // starts a new one
+                    let _ = self.storage.close();
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Runs the operation on the default drive, which holds the adapter files, and switches back
+    /// to the drive selected by the adapter.
+    fn in_default_drive<T>(
+        &mut self,
+        operation: impl FnOnce(&mut Self) -> Result<T, Fault>,
+    ) -> Result<T, Fault> {
+        self.storage
+            .set_drive(Drive::default())
+            .map_err(Into::into)?;
+        let result = operation(self);
+        let restored = self.storage.set_drive(self.drive).map_err(Into::into);
+        let value = result?;
+        restored?;
+        Ok(value)
+    }
+
+    fn attach(&mut self, buffer: &mut Buffer) -> Result<usize, Fault> {
+        buffer.fill(0);
+        // The drive selected on the previous card may not exist on this one
+        self.drive = Drive::default();
+        // Drops the session of the removed card before the drive is switched
+        self.storage.attach().map_err(Into::into)?;
+        self.in_default_drive(|worker| {
+            let size = match worker
+                .storage
+                .file_size(CONFIG_FILE_NAME)
+                .map_err(Into::into)?
+            {
+                Some(_) => worker
+                    .storage
+                    .read_file(CONFIG_FILE_NAME, 0, buffer)
+                    .map_err(Into::into)?,
+                None => 0,
+            };
+            worker.storage.close().map_err(Into::into)?;
+            Ok(size)
+        })
+    }
+
+    fn log(&mut self, buffer: &mut Buffer, len: usize) -> Result<usize, Fault> {
+        let len = stamp(buffer, len, DateTime(self.storage.now()));
+        let text = &buffer[..len];
+        let size = self.storage.file_size(LOG_FILE_NAME).map_err(Into::into)?;
+        if size.unwrap_or(0) + text.len() > MAX_LOG_SIZE {
+            self.storage
+                .rename_file(LOG_FILE_NAME, OLD_LOG_FILE_NAME)
+                .map_err(Into::into)?;
+        }
+        let size = self
+            .storage
+            .append_file(LOG_FILE_NAME, text)
+            .map_err(Into::into)?;
+        self.storage.close().map_err(Into::into)?;
+        Ok(size)
+    }
+
+    fn read(
+        &mut self,
+        name: &str,
+        offset: usize,
+        buffer: &mut Buffer,
+        verify: bool,
+    ) -> Result<usize, Fault> {
+        buffer.fill(0);
+        let size = self
+            .storage
+            .read_file(name, offset, buffer)
+            .map_err(Into::into)?;
+        if verify && size > 0 {
+            self.verify(name, offset / IO_BUFFER_SIZE, &buffer[..size])?;
+        }
+        Ok(size)
+    }
+
+    fn list(&mut self, offset: usize, buffer: &mut Buffer, sizes: bool) -> Result<usize, Fault> {
+        buffer.fill(0);
+        let entry_len = if sizes { 6 } else { 2 };
+        let mut pos = 0;
+        let mut len = 0;
+        self.storage
+            .list_files(&mut |name, size| {
+                let Some(address) = address_of(name) else {
+                    return;
+                };
+                let words = size.div_ceil(2).min(u32::MAX as usize) as u32;
+                let mut entry = [0; 6];
+                entry[..2].copy_from_slice(&address.to_le_bytes());
+                entry[2..4].copy_from_slice(&((words >> 16) as u16).to_le_bytes());
+                entry[4..].copy_from_slice(&(words as u16).to_le_bytes());
+                // Entries are cut at the buffer ends, the next buffer goes on from the same byte
+                for &byte in &entry[..entry_len] {
+                    if pos >= offset && len < buffer.len() {
+                        buffer[len] = byte;
+                        len += 1;
+                    }
+                    pos += 1;
+                }
+            })
+            .map_err(Into::into)?;
+        Ok(len)
+    }
+
+    /// Compares the block with its checksum, blocks written by other means have no checksum.
+    fn verify(&mut self, name: &str, block: usize, data: &[u8]) -> Result<(), Fault> {
+        let checksums = checksum_name(name);
+        if self
+            .storage
+            .file_size(&checksums)
+            .map_err(Into::into)?
+            .is_none()
+        {
+            return Ok(());
+        }
+
+        let mut checksum = [0; CHECKSUM_SIZE];
+        let offset = block * CHECKSUM_SIZE;
+        let size = self
+            .storage
+            .read_file(&checksums, offset, &mut checksum)
+            .map_err(Into::into)?;
+        if size == CHECKSUM_SIZE && u32::from_le_bytes(checksum) != crc32(data) {
+            return Err(AppError::ChecksumMismatch.into());
+        }
+        Ok(())
+    }
+
+    fn append(&mut self, name: &str, data: &[u8], checksum: bool) -> Result<usize, Fault> {
+        let size = self.storage.append_file(name, data).map_err(Into::into)?;
+        if checksum {
+            let checksum = crc32(data).to_le_bytes();
+            self.storage
+                .append_file(&checksum_name(name), &checksum)
+                .map_err(Into::into)?;
+        }
+        Ok(size)
+    }
+
+    /// Removes the file together with its checksums.
+    fn delete(&mut self, name: &str) -> Result<(), Fault> {
+        self.storage.remove_file(name).map_err(Into::into)?;
+        self.storage
+            .remove_file(&checksum_name(name))
+            .map_err(Into::into)
+    }
+
+    /// Renames the file together with its checksums, returns `false` if there is no file.
+    fn rename(&mut self, from: &str, to: &str) -> Result<bool, Fault> {
+        if !self.storage.rename_file(from, to).map_err(Into::into)? {
+            return Ok(false);
+        }
+
+        let (from, to) = (checksum_name(from), checksum_name(to));
+        if !self.storage.rename_file(&from, &to).map_err(Into::into)? {
+            // Checksums of the replaced file don't match the new one
+            self.storage.remove_file(&to).map_err(Into::into)?;
+        }
+        Ok(true)
+    }
+
+    fn remove(&mut self, name: &str, backups: u8) -> Result<(), Fault> {
+        if backups == 0 {
+            return self.delete(name);
+        }
+
+        self.delete(&backup_name(name, backups))?;
+        for generation in (1..backups).rev() {
+            let from = backup_name(name, generation);
+            let to = backup_name(name, generation + 1);
+            self.rename(&from, &to)?;
+        }
+        self.rename(name, &backup_name(name, 1))?;
+        Ok(())
+    }
+
+    /// Removes the file like `remove` if it exists, so backups don't move down without a file
+    /// taking their place.
+    fn remove_existing(&mut self, name: &str, backups: u8) -> Result<(), Fault> {
+        if self.storage.file_size(name).map_err(Into::into)?.is_some() {
+            self.remove(name, backups)?;
+        }
+        Ok(())
+    }
+
+    fn move_file(&mut self, from: &str, to: &str, backups: u8) -> Result<(), Fault> {
+        if self.storage.file_size(from).map_err(Into::into)?.is_none() {
+            return Err(AppError::AddressNotFound.into());
+        }
+        if from != to {
+            self.remove_existing(to, backups)?;
+            self.rename(from, to)?;
+        }
+        Ok(())
+    }
+
+    fn commit(&mut self, name: &str, backups: u8) -> Result<(), Fault> {
+        self.remove_existing(name, backups)?;
+        self.rename(&temp_name(name), name)?;
+        Ok(())
+    }
+
+    fn restore(&mut self, name: &str, backups: u8) -> Result<(), Fault> {
+        if !self.rename(&backup_name(name, 1), name)? {
+            return Err(AppError::BackupNotFound.into());
+        }
+
+        for generation in 2..=backups {
+            let from = backup_name(name, generation);
+            let to = backup_name(name, generation - 1);
+            self.rename(&from, &to)?;
+        }
+        Ok(())
+    }
+}
+
+/// Whole KB in `bytes`, saturated to what the response can carry.
+fn kilobytes(bytes: u64) -> usize {
+    usize::try_from(bytes / 1024).unwrap_or(usize::MAX)
+}
@@ -0,0 +1,1159 @@
+mod common;
+
+use common::{words_to_bytes, Harness, Leds, FILE_NOT_FOUND, TIMEOUT_READ_BUFFER};
+use sm2m_protocol::{
+    adapter::IO_BUFFER_SIZE,
+    bus::{input, output, Parity},
+    checksum::crc32,
+    log::MAX_LOG_SIZE,
+    worker::MAX_ATTEMPTS,
+    Config, Drives, Layout,
+};
+
+#[test]
+fn reset_acknowledges_and_turns_leds_off() {
+    let mut adapter = Harness::new();
+
+    assert_eq!(adapter.send(input::Action::Reset), output::Frame::Ack);
+    assert_eq!(adapter.leds(), Leds::default());
+}
+
+#[test]
+fn check_status_reports_card_presence() {
+    let mut adapter = Harness::new();
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+
+    adapter.set_attached(false);
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Error(1)
+    );
+    assert!(adapter.leds().system_error);
+}
+
+#[test]
+fn check_status_is_answered_from_detect_pin() {
+    let mut adapter = Harness::new();
+    adapter.detect_card(true);
+    adapter.hold_worker(true);
+
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+    adapter.detect_card(false);
+    assert!(adapter.leds().system_error);
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Error(1)
+    );
+    assert_eq!(adapter.pending_requests(), 0);
+
+    adapter.send(input::Action::Reset);
+    assert!(adapter.leds().system_error);
+}
+
+#[test]
+fn card_removal_aborts_transfer() {
+    let mut adapter = Harness::new();
+    adapter.detect_card(true);
+    adapter.put_file("1", &words_to_bytes(&[1, 2]));
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Read);
+    assert_eq!(
+        adapter.command(input::Frame::Data(0)),
+        output::Frame::Data(1)
+    );
+
+    adapter.detect_card(false);
+    assert!(!adapter.leds().read);
+    assert_eq!(
+        adapter.command(input::Frame::Data(0)),
+        output::Frame::Error(1)
+    );
+}
+
+#[test]
+fn inserted_card_is_initialised() {
+    let mut adapter = Harness::new();
+    adapter.detect_card(true);
+    assert_eq!(adapter.mounts(), 1);
+
+    adapter.detect_card(false);
+    adapter.detect_card(true);
+    assert_eq!(adapter.mounts(), 2);
+    assert!(!adapter.leds().system_error);
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+}
+
+#[test]
+fn configuration_file_is_applied_on_insertion() {
+    let mut adapter = Harness::new();
+    let defaults = Config {
+        checksums: true,
+        ..Config::default()
+    };
+    adapter.configure(defaults);
+    adapter.put_file("ADAPTER.CFG", b"backups = 1\r\nbyte_order = big\r\n");
+    adapter.detect_card(true);
+
+    adapter.write_file(1, &[0x0102]);
+    adapter.write_file(1, &[0x0304]);
+    assert_eq!(adapter.file("1"), Some(vec![0x03, 0x04]));
+    assert_eq!(adapter.file("1.BK1"), Some(vec![0x01, 0x02]));
+    assert_eq!(adapter.read_file(1, 1), [0x0304]);
+
+    adapter.detect_card(false);
+    adapter.put_file("ADAPTER.CFG", b"");
+    adapter.detect_card(true);
+    assert_eq!(adapter.config(), defaults);
+}
+
+#[test]
+fn spi_clock_is_set_once_card_is_initialised() {
+    let mut adapter = Harness::new();
+    let defaults = Config {
+        spi_clock: 25_000,
+        ..Config::default()
+    };
+    adapter.configure(defaults);
+    adapter.put_file("ADAPTER.CFG", b"spi_clock = 4000");
+    adapter.detect_card(true);
+    assert_eq!(adapter.mounts(), 1);
+    assert_eq!(adapter.spi_clock(), 4000);
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+
+    // A card without the setting gets the compiled in clock back
+    adapter.detect_card(false);
+    adapter.put_file("ADAPTER.CFG", b"");
+    adapter.detect_card(true);
+    assert_eq!(adapter.spi_clock(), 25_000);
+}
+
+#[test]
+fn unexpected_commands_are_rejected() {
+    let mut adapter = Harness::new();
+    assert_eq!(adapter.command(input::Frame::Read), output::Frame::Error(2));
+
+    adapter.send(input::Action::Reset);
+    adapter.command(input::Frame::Address(1));
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Error(3)
+    );
+}
+
+#[test]
+fn error_is_repeated_until_reset() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Write);
+
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Error(2)
+    );
+    assert_eq!(adapter.send(input::Action::Stop), output::Frame::Ack);
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+    assert!(!adapter.leds().system_error);
+}
+
+#[test]
+fn write_stores_words_in_address_file() {
+    let mut adapter = Harness::new();
+    let words = [0x1234, 0xFFFF, 0x0000, 0x8001];
+
+    adapter.write_file(7, &words);
+
+    assert_eq!(adapter.file("7"), Some(words_to_bytes(&words)));
+    assert_eq!(adapter.leds(), Leds::default());
+}
+
+#[test]
+fn write_replaces_existing_file() {
+    let mut adapter = Harness::new();
+    adapter.put_file("7", &[1, 2, 3, 4, 5, 6]);
+
+    adapter.write_file(7, &[0xABCD]);
+
+    assert_eq!(adapter.file("7"), Some(vec![0xCD, 0xAB]));
+}
+
+#[test]
+fn write_turns_write_led_on() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Write);
+
+    assert!(adapter.leds().write);
+}
+
+#[test]
+fn read_returns_written_words() {
+    let mut adapter = Harness::new();
+    let words = [0x0102, 0xA0B0, 0x7FFF];
+    adapter.write_file(3, &words);
+
+    assert_eq!(adapter.read_file(3, words.len()), words);
+}
+
+#[test]
+fn read_leaves_file_untouched() {
+    let mut adapter = Harness::new();
+    let words = [0x0102, 0xA0B0, 0x7FFF];
+    adapter.write_file(3, &words);
+
+    adapter.read_file(3, 2);
+
+    assert_eq!(adapter.file("3"), Some(words_to_bytes(&words)));
+}
+
+#[test]
+fn read_turns_read_led_on() {
+    let mut adapter = Harness::new();
+    adapter.put_file("1", &[0, 0]);
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Read);
+
+    assert!(adapter.leds().read);
+}
+
+#[test]
+fn read_of_missing_file_reports_storage_error() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Address(9));
+
+    assert_eq!(
+        adapter.command(input::Frame::Read),
+        output::Frame::Error(FILE_NOT_FOUND)
+    );
+}
+
+#[test]
+fn error_detail_tells_what_failed() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Address(9));
+    adapter.command(input::Frame::Read);
+
+    // Opcode, opening the file, address, offset and no parameters, then zeros
+    assert_eq!(
+        adapter.error_detail(8),
+        [FILE_NOT_FOUND, 4, 9, 0, 0, 0, 0, 0]
+    );
+    assert_eq!(adapter.send(input::Action::Stop), output::Frame::Ack);
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+}
+
+#[test]
+fn error_detail_is_kept_after_reset() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Read);
+    adapter.command(input::Frame::CheckStatus);
+    adapter.send(input::Action::Reset);
+
+    assert_eq!(adapter.error_detail(3), [2, 0, 0]);
+}
+
+#[test]
+fn acknowledged_error_returns_to_ready() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Address(9));
+    adapter.command(input::Frame::Read);
+
+    assert_eq!(
+        adapter.command(input::Frame::AcknowledgeError),
+        output::Frame::Ack
+    );
+    assert!(!adapter.leds().system_error);
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+}
+
+#[test]
+fn transient_read_errors_are_retried() {
+    let mut adapter = Harness::new();
+    let words = [0x0102, 0xA0B0];
+    adapter.write_file(3, &words);
+
+    adapter.fail_reads(MAX_ATTEMPTS - 1);
+    assert_eq!(adapter.read_file(3, 2), words);
+}
+
+#[test]
+fn persistent_read_errors_are_reported() {
+    let mut adapter = Harness::new();
+    adapter.write_file(3, &[0x0102]);
+    adapter.fail_reads(MAX_ATTEMPTS);
+    adapter.command(input::Frame::Address(3));
+
+    assert_eq!(
+        adapter.command(input::Frame::Read),
+        output::Frame::Error(TIMEOUT_READ_BUFFER)
+    );
+}
+
+#[test]
+fn transient_in_place_write_errors_are_reported() {
+    let mut adapter = Harness::new();
+    adapter.write_file(3, &[1, 2]);
+    adapter.fail_writes(1);
+
+    adapter.seek(3, 0);
+    assert_eq!(adapter.command(input::Frame::Write), output::Frame::Ack);
+    assert_eq!(adapter.command(input::Frame::Data(5)), output::Frame::Ack);
+    assert_eq!(
+        adapter.send(input::Action::Stop),
+        output::Frame::Error(TIMEOUT_READ_BUFFER)
+    );
+}
+
+#[test]
+fn read_starts_at_seek_offset() {
+    let mut adapter = Harness::new();
+    adapter.write_file(3, &[1, 2, 3, 4]);
+
+    adapter.seek(3, 2);
+    assert_eq!(adapter.command(input::Frame::Read), output::Frame::Ack);
+    assert_eq!(
+        adapter.command(input::Frame::Data(0)),
+        output::Frame::Data(3)
+    );
+    assert_eq!(
+        adapter.command(input::Frame::Data(0)),
+        output::Frame::Data(4)
+    );
+}
+
+#[test]
+fn write_at_seek_offset_overwrites_in_place() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        backups: 1,
+        checksums: true,
+        ..Config::default()
+    });
+    adapter.write_file(3, &[1, 2, 3, 4]);
+
+    adapter.seek(3, 1);
+    assert_eq!(adapter.command(input::Frame::Write), output::Frame::Ack);
+    assert_eq!(adapter.command(input::Frame::Data(9)), output::Frame::Ack);
+    assert_eq!(adapter.send(input::Action::Stop), output::Frame::Ack);
+
+    assert_eq!(adapter.file("3"), Some(words_to_bytes(&[1, 9, 3, 4])));
+    assert_eq!(adapter.file("3.BK1"), None);
+    // The checksums no longer match, so they are dropped
+    assert_eq!(adapter.file("3.CRC"), None);
+    assert_eq!(adapter.read_file(3, 4), [1, 9, 3, 4]);
+}
+
+#[test]
+fn write_after_seek_is_refused_before_data_outside_file() {
+    let mut adapter = Harness::new();
+    adapter.write_file(3, &[1, 2]);
+
+    // No file to overwrite
+    adapter.seek(4, 0);
+    assert_eq!(
+        adapter.command(input::Frame::Write),
+        output::Frame::Error(50)
+    );
+    adapter.send(input::Action::Reset);
+
+    adapter.seek(3, 3);
+    assert_eq!(
+        adapter.command(input::Frame::Write),
+        output::Frame::Error(52)
+    );
+    adapter.send(input::Action::Reset);
+
+    // The end of the file is where the file is extended from
+    adapter.seek(3, 2);
+    assert_eq!(adapter.command(input::Frame::Write), output::Frame::Ack);
+    assert_eq!(adapter.command(input::Frame::Data(3)), output::Frame::Ack);
+    assert_eq!(adapter.send(input::Action::Stop), output::Frame::Ack);
+    assert_eq!(adapter.file("3"), Some(words_to_bytes(&[1, 2, 3])));
+    assert_eq!(adapter.file("4"), None);
+}
+
+#[test]
+fn append_keeps_existing_content() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        backups: 1,
+        checksums: true,
+        ..Config::default()
+    });
+    adapter.write_file(3, &[1, 2]);
+
+    adapter.command(input::Frame::Address(3));
+    assert_eq!(adapter.command(input::Frame::Append), output::Frame::Ack);
+    assert_eq!(adapter.command(input::Frame::Data(3)), output::Frame::Ack);
+    assert_eq!(adapter.send(input::Action::Stop), output::Frame::Ack);
+
+    assert_eq!(adapter.file("3"), Some(words_to_bytes(&[1, 2, 3])));
+    assert_eq!(adapter.file("3.BK1"), None);
+    assert_eq!(adapter.file("3.CRC"), None);
+}
+
+#[test]
+fn append_creates_missing_file() {
+    let mut adapter = Harness::new();
+    let words: Vec<u16> = (0..IO_BUFFER_SIZE as u16).collect();
+
+    adapter.command(input::Frame::Address(4));
+    adapter.command(input::Frame::Append);
+    for word in &words {
+        assert_eq!(
+            adapter.command(input::Frame::Data(*word)),
+            output::Frame::Ack
+        );
+    }
+    assert_eq!(adapter.send(input::Action::Stop), output::Frame::Ack);
+
+    assert_eq!(adapter.file("4"), Some(words_to_bytes(&words)));
+}
+
+#[test]
+fn exists_tells_whether_address_has_data() {
+    let mut adapter = Harness::new();
+    adapter.write_file(3, &[1]);
+
+    adapter.command(input::Frame::Address(3));
+    assert_eq!(
+        adapter.command(input::Frame::Exists),
+        output::Frame::Data(1)
+    );
+    adapter.send(input::Action::Stop);
+    adapter.command(input::Frame::Address(4));
+    assert_eq!(
+        adapter.command(input::Frame::Exists),
+        output::Frame::Data(0)
+    );
+}
+
+#[test]
+fn size_is_answered_in_words() {
+    let mut adapter = Harness::new();
+    let words = 0x0001_1170;
+    adapter.put_file("3", &vec![0; words * 2]);
+
+    adapter.command(input::Frame::Address(3));
+    assert_eq!(adapter.command(input::Frame::Size), output::Frame::Data(1));
+    assert_eq!(
+        adapter.command(input::Frame::Data(0)),
+        output::Frame::Data(0x1170)
+    );
+
+    // The address stays selected
+    assert_eq!(adapter.command(input::Frame::Read), output::Frame::Ack);
+}
+
+#[test]
+fn read_ends_with_dteo_at_end_of_file() {
+    let mut adapter = Harness::new();
+    adapter.write_file(3, &[1, 2]);
+
+    adapter.command(input::Frame::Address(3));
+    adapter.command(input::Frame::Read);
+    assert_eq!(
+        adapter.command(input::Frame::Data(0)),
+        output::Frame::Data(1)
+    );
+    assert_eq!(
+        adapter.command(input::Frame::Data(0)),
+        output::Frame::Data(2)
+    );
+    assert_eq!(adapter.command(input::Frame::Data(0)), output::Frame::End);
+    assert_eq!(adapter.command(input::Frame::Data(0)), output::Frame::End);
+}
+
+#[test]
+fn long_address_reaches_full_range() {
+    let mut adapter = Harness::new();
+    for address in [64, 65535] {
+        assert_eq!(
+            adapter.command(input::Frame::LongAddress),
+            output::Frame::Ack
+        );
+        assert_eq!(
+            adapter.command(input::Frame::Data(address)),
+            output::Frame::Ack
+        );
+        assert_eq!(adapter.command(input::Frame::Write), output::Frame::Ack);
+        assert_eq!(
+            adapter.command(input::Frame::Data(address)),
+            output::Frame::Ack
+        );
+        assert_eq!(adapter.send(input::Action::Stop), output::Frame::Ack);
+    }
+
+    assert_eq!(adapter.file("64"), Some(words_to_bytes(&[64])));
+    assert_eq!(adapter.file("65535"), Some(words_to_bytes(&[65535])));
+}
+
+#[test]
+fn list_answers_stored_addresses() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        backups: 1,
+        drives: Drives::Directories,
+        ..Config::default()
+    });
+    adapter.write_file(12, &[1]);
+    adapter.write_file(12, &[1, 2]);
+    adapter.put_file("65535", &[0; 5]);
+    adapter.put_file("007", &[0; 2]);
+    adapter.put_file("ADAPTER.CFG", b"backups = 1");
+    adapter.put_file("DRIVE1/3", &[0; 2]);
+
+    assert_eq!(adapter.list(input::Frame::List), [12, 65535]);
+    assert_eq!(
+        adapter.list(input::Frame::ListSizes),
+        [12, 0, 2, 65535, 0, 3]
+    );
+
+    adapter.select_drive(1);
+    assert_eq!(adapter.list(input::Frame::List), [3]);
+    adapter.select_drive(2);
+    assert_eq!(adapter.list(input::Frame::List), []);
+}
+
+#[test]
+fn list_spans_multiple_buffers() {
+    let mut adapter = Harness::new();
+    let mut addresses: Vec<u16> = (0..1000).collect();
+    for address in &addresses {
+        adapter.put_file(&address.to_string(), &[0; 4]);
+    }
+    addresses.sort_by_key(u16::to_string);
+
+    let expected: Vec<u16> = addresses.iter().flat_map(|&a| [a, 0, 2]).collect();
+    assert_eq!(adapter.list(input::Frame::ListSizes), expected);
+}
+
+#[test]
+fn list_is_rejected_with_image_layout() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        layout: Layout::Image { sector_size: 512 },
+        ..Config::default()
+    });
+
+    assert_eq!(adapter.command(input::Frame::List), output::Frame::Error(2));
+}
+
+#[test]
+fn drives_map_to_directories() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        drives: Drives::Directories,
+        ..Config::default()
+    });
+    adapter.select_drive(1);
+    adapter.write_file(3, &[1, 2]);
+    assert_eq!(adapter.file("DRIVE1/3"), Some(words_to_bytes(&[1, 2])));
+    assert_eq!(adapter.file("3"), None);
+
+    adapter.select_drive(0);
+    adapter.command(input::Frame::Address(3));
+    assert_eq!(
+        adapter.command(input::Frame::Exists),
+        output::Frame::Data(0)
+    );
+}
+
+#[test]
+fn drives_map_to_partitions() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        drives: Drives::Partitions,
+        ..Config::default()
+    });
+    adapter.put_file("1:3", &words_to_bytes(&[7]));
+    adapter.select_drive(1);
+    assert_eq!(adapter.read_file(3, 1), [7]);
+}
+
+#[test]
+fn unknown_drive_is_rejected() {
+    let mut adapter = Harness::new();
+    assert_eq!(adapter.command(input::Frame::Drive), output::Frame::Ack);
+    assert_eq!(
+        adapter.command(input::Frame::Data(1)),
+        output::Frame::Error(49)
+    );
+    assert_eq!(adapter.error_detail(2), [49, 0]);
+    adapter.send(input::Action::Reset);
+
+    adapter.write_file(3, &[1]);
+    assert_eq!(adapter.file("3"), Some(words_to_bytes(&[1])));
+}
+
+#[test]
+fn adapter_files_stay_in_default_drive() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        drives: Drives::Directories,
+        log: true,
+        ..Config::default()
+    });
+    adapter.select_drive(2);
+    adapter.write_file(1, &[1]);
+
+    assert_eq!(adapter.file("DRIVE2/1"), Some(words_to_bytes(&[1])));
+    assert_eq!(
+        adapter.file("ADAPTER.LOG").as_deref(),
+        Some(&b"1970-01-01 00:00:00 boot\n1970-01-01 00:00:00 write address=1 words=1\n"[..])
+    );
+}
+
+#[test]
+fn transfers_span_multiple_buffers() {
+    let mut adapter = Harness::new();
+    let words: Vec<u16> = (0..(IO_BUFFER_SIZE as u16)).collect();
+
+    adapter.write_file(42, &words);
+    assert_eq!(adapter.file("42"), Some(words_to_bytes(&words)));
+
+    assert_eq!(adapter.read_file(42, words.len()), words);
+}
+
+#[test]
+fn parity_is_not_verified_by_default() {
+    let mut adapter = Harness::new();
+    let action = input::Action::Data(0x0000, Parity::of(0x0001));
+
+    assert_eq!(adapter.send(action), output::Frame::Ack);
+    assert!(!adapter.output_parity());
+}
+
+#[test]
+fn parity_mismatch_is_reported() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        parity: true,
+        ..Config::default()
+    });
+    assert!(adapter.output_parity());
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+
+    let action = input::Action::Data(0x0000, Parity::of(0x0001));
+    assert_eq!(adapter.send(action), output::Frame::Error(45));
+    assert!(adapter.leds().system_error);
+}
+
+#[test]
+fn parity_mismatch_drops_only_the_word() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        parity: true,
+        ..Config::default()
+    });
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Write);
+    adapter.command(input::Frame::Data(0x0102));
+
+    let action = input::Action::Data(0x0304, Parity::of(0x0305));
+    assert_eq!(adapter.send(action), output::Frame::Error(45));
+    assert_eq!(
+        adapter.command(input::Frame::Data(0x0304)),
+        output::Frame::Ack
+    );
+    assert_eq!(adapter.send(input::Action::Stop), output::Frame::Ack);
+
+    assert_eq!(adapter.file("1"), Some(words_to_bytes(&[0x0102, 0x0304])));
+}
+
+#[test]
+fn storage_is_closed_when_transfer_ends() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Write);
+    adapter.command(input::Frame::Data(0x0102));
+    assert!(adapter.is_storage_open());
+
+    adapter.send(input::Action::Stop);
+    assert!(!adapter.is_storage_open());
+
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Read);
+    assert!(adapter.is_storage_open());
+
+    adapter.send(input::Action::Reset);
+    assert!(!adapter.is_storage_open());
+
+    adapter.command(input::Frame::Address(2));
+    adapter.command(input::Frame::Read);
+    assert!(!adapter.is_storage_open());
+}
+
+#[test]
+fn set_time_passes_clock_to_storage() {
+    let mut adapter = Harness::new();
+    let seconds: u32 = 1_700_000_000;
+
+    assert_eq!(adapter.command(input::Frame::SetTime), output::Frame::Ack);
+    let high = (seconds >> 16) as u16;
+    assert_eq!(
+        adapter.command(input::Frame::Data(high)),
+        output::Frame::Ack
+    );
+    assert_eq!(adapter.time(), None);
+    let low = seconds as u16;
+    assert_eq!(adapter.command(input::Frame::Data(low)), output::Frame::Ack);
+    assert_eq!(adapter.time(), Some(seconds));
+
+    // Back in Ready mode
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+}
+
+#[test]
+fn set_time_rejects_time_before_1980() {
+    let mut adapter = Harness::new();
+
+    adapter.command(input::Frame::SetTime);
+    adapter.command(input::Frame::Data(0x0000));
+    assert_eq!(
+        adapter.command(input::Frame::Data(0x0001)),
+        output::Frame::Error(46)
+    );
+    assert_eq!(adapter.time(), None);
+
+    // Only the time is rejected
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+}
+
+#[test]
+fn write_keeps_configured_backup_generations() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        backups: 2,
+        ..Config::default()
+    });
+
+    for word in 1..=4 {
+        adapter.write_file(1, &[word]);
+    }
+
+    assert_eq!(adapter.file("1"), Some(words_to_bytes(&[4])));
+    assert_eq!(adapter.file("1.BK1"), Some(words_to_bytes(&[3])));
+    assert_eq!(adapter.file("1.BK2"), Some(words_to_bytes(&[2])));
+    assert_eq!(adapter.file("1.BK3"), None);
+}
+
+#[test]
+fn restore_brings_back_previous_generation() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        backups: 2,
+        ..Config::default()
+    });
+    for word in 1..=3 {
+        adapter.write_file(1, &[word]);
+    }
+
+    adapter.command(input::Frame::Address(1));
+    assert_eq!(adapter.command(input::Frame::Restore), output::Frame::Ack);
+    assert!(!adapter.is_storage_open());
+
+    assert_eq!(adapter.file("1"), Some(words_to_bytes(&[2])));
+    assert_eq!(adapter.file("1.BK1"), Some(words_to_bytes(&[1])));
+    assert_eq!(adapter.file("1.BK2"), None);
+    assert_eq!(adapter.read_file(1, 1), [2]);
+}
+
+#[test]
+fn restore_without_backup_reports_error() {
+    let mut adapter = Harness::new();
+    adapter.write_file(1, &[1]);
+
+    adapter.command(input::Frame::Address(1));
+    assert_eq!(
+        adapter.command(input::Frame::Restore),
+        output::Frame::Error(47)
+    );
+    assert_eq!(adapter.file("1"), Some(words_to_bytes(&[1])));
+}
+
+#[test]
+fn delete_keeps_file_as_backup() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        backups: 1,
+        checksums: true,
+        ..Config::default()
+    });
+    adapter.write_file(5, &[1, 2]);
+
+    adapter.command(input::Frame::Address(5));
+    assert_eq!(adapter.command(input::Frame::Delete), output::Frame::Ack);
+    assert!(!adapter.is_storage_open());
+    assert_eq!(adapter.file("5"), None);
+    assert_eq!(adapter.file("5.CRC"), None);
+    assert_eq!(adapter.file("5.BK1"), Some(words_to_bytes(&[1, 2])));
+    assert!(adapter.file("5.CR1").is_some());
+
+    // Deleting the missing file again leaves its backup in place
+    adapter.command(input::Frame::Address(5));
+    assert_eq!(adapter.command(input::Frame::Delete), output::Frame::Ack);
+    assert_eq!(adapter.file("5.BK1"), Some(words_to_bytes(&[1, 2])));
+
+    adapter.command(input::Frame::Address(5));
+    assert_eq!(adapter.command(input::Frame::Restore), output::Frame::Ack);
+    assert_eq!(adapter.read_file(5, 2), [1, 2]);
+}
+
+#[test]
+fn write_after_delete_keeps_backup_of_deleted_file() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        backups: 1,
+        ..Config::default()
+    });
+    adapter.write_file(5, &[1, 2]);
+    adapter.command(input::Frame::Address(5));
+    assert_eq!(adapter.command(input::Frame::Delete), output::Frame::Ack);
+
+    adapter.write_file(5, &[3, 4]);
+    assert_eq!(adapter.file("5.BK1"), Some(words_to_bytes(&[1, 2])));
+
+    adapter.command(input::Frame::Address(5));
+    assert_eq!(adapter.command(input::Frame::Restore), output::Frame::Ack);
+    assert_eq!(adapter.read_file(5, 2), [1, 2]);
+}
+
+#[test]
+fn delete_without_backups_removes_file() {
+    let mut adapter = Harness::new();
+    adapter.write_file(5, &[1]);
+
+    adapter.command(input::Frame::Address(5));
+    assert_eq!(adapter.command(input::Frame::Delete), output::Frame::Ack);
+    assert_eq!(adapter.file("5"), None);
+    assert_eq!(adapter.file("5.BK1"), None);
+}
+
+#[test]
+fn move_renames_file() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        log: true,
+        ..Config::default()
+    });
+    adapter.write_file(1, &[1, 2]);
+
+    adapter.command(input::Frame::Address(1));
+    assert_eq!(adapter.command(input::Frame::Move), output::Frame::Ack);
+    assert_eq!(adapter.command(input::Frame::Data(300)), output::Frame::Ack);
+    assert!(!adapter.is_storage_open());
+
+    assert_eq!(adapter.file("1"), None);
+    assert_eq!(adapter.file("300"), Some(words_to_bytes(&[1, 2])));
+    let log = String::from_utf8(adapter.file("ADAPTER.LOG").unwrap()).unwrap();
+    assert!(log.ends_with("move address=1 to=300\n"));
+}
+
+#[test]
+fn move_keeps_replaced_file_as_backup() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        backups: 1,
+        ..Config::default()
+    });
+    adapter.write_file(1, &[1]);
+    adapter.write_file(2, &[2]);
+
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Move);
+    assert_eq!(adapter.command(input::Frame::Data(2)), output::Frame::Ack);
+
+    assert_eq!(adapter.file("1"), None);
+    assert_eq!(adapter.file("2"), Some(words_to_bytes(&[1])));
+    assert_eq!(adapter.file("2.BK1"), Some(words_to_bytes(&[2])));
+}
+
+#[test]
+fn move_of_missing_address_reports_error() {
+    let mut adapter = Harness::new();
+    adapter.write_file(2, &[2]);
+
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Move);
+    assert_eq!(
+        adapter.command(input::Frame::Data(2)),
+        output::Frame::Error(50)
+    );
+    assert_eq!(adapter.error_detail(3), [50, 19, 1]);
+    assert_eq!(adapter.file("2"), Some(words_to_bytes(&[2])));
+}
+
+#[test]
+fn delete_and_move_are_rejected_with_image_layout() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        layout: Layout::Image { sector_size: 512 },
+        ..Config::default()
+    });
+
+    for command in [input::Frame::Delete, input::Frame::Move] {
+        adapter.command(input::Frame::Address(1));
+        assert_eq!(adapter.command(command), output::Frame::Error(3));
+        adapter.send(input::Action::Reset);
+    }
+}
+
+#[test]
+fn capacity_is_answered_in_kilobytes() {
+    let mut adapter = Harness::new();
+    adapter.set_capacity(0x1_0002 * 1024);
+    adapter.put_file("1", &[0; 3 * 1024]);
+
+    assert_eq!(
+        adapter.command(input::Frame::Capacity),
+        output::Frame::Data(1)
+    );
+    for word in [2, 0, 0xFFFF] {
+        assert_eq!(
+            adapter.command(input::Frame::Data(0)),
+            output::Frame::Data(word)
+        );
+    }
+
+    // Back in Ready mode
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Ack
+    );
+}
+
+#[test]
+fn write_is_refused_below_reserve() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        reserve: 8,
+        ..Config::default()
+    });
+    adapter.set_capacity(16 * 1024);
+    adapter.write_file(1, &[1]);
+    adapter.put_file("2", &[0; 8 * 1024]);
+
+    for command in [input::Frame::Write, input::Frame::Append] {
+        adapter.command(input::Frame::Address(1));
+        assert_eq!(adapter.command(command), output::Frame::Error(51));
+        assert_eq!(adapter.error_detail(3), [51, 0, 1]);
+        adapter.send(input::Action::Reset);
+    }
+    assert_eq!(adapter.file("1"), Some(words_to_bytes(&[1])));
+
+    // Reads and freeing space are still possible
+    assert_eq!(adapter.read_file(1, 1), [1]);
+    adapter.command(input::Frame::Address(2));
+    assert_eq!(adapter.command(input::Frame::Delete), output::Frame::Ack);
+    adapter.write_file(1, &[2]);
+    assert_eq!(adapter.file("1"), Some(words_to_bytes(&[2])));
+}
+
+#[test]
+fn interrupted_write_leaves_file_untouched() {
+    let mut adapter = Harness::new();
+    adapter.put_file("1", &words_to_bytes(&[1, 2, 3]));
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Write);
+    for word in 0..IO_BUFFER_SIZE as u16 {
+        adapter.command(input::Frame::Data(word));
+    }
+
+    adapter.send(input::Action::Reset);
+
+    assert_eq!(adapter.file("1"), Some(words_to_bytes(&[1, 2, 3])));
+    assert!(adapter.file("1.TMP").is_some());
+}
+
+#[test]
+fn write_discards_leftover_of_interrupted_session() {
+    let mut adapter = Harness::new();
+    adapter.put_file("1.TMP", &[0xAA; 64]);
+
+    adapter.write_file(1, &[0x0102]);
+
+    assert_eq!(adapter.file("1"), Some(vec![0x02, 0x01]));
+    assert_eq!(adapter.file("1.TMP"), None);
+}
+
+#[test]
+fn written_blocks_get_checksums() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        checksums: true,
+        ..Config::default()
+    });
+    let words: Vec<u16> = (0..IO_BUFFER_SIZE as u16 / 2 + 10).collect();
+
+    adapter.write_file(1, &words);
+
+    let data = words_to_bytes(&words);
+    let (first, second) = data.split_at(IO_BUFFER_SIZE);
+    let checksums = [crc32(first), crc32(second)].map(u32::to_le_bytes).concat();
+    assert_eq!(adapter.file("1.CRC"), Some(checksums));
+    assert_eq!(adapter.file("1.CRT"), None);
+    assert_eq!(adapter.read_file(1, words.len()), words);
+}
+
+#[test]
+fn corrupted_block_is_reported_on_read() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        checksums: true,
+        ..Config::default()
+    });
+    adapter.write_file(1, &[1, 2, 3]);
+    adapter.put_file("1", &words_to_bytes(&[1, 0, 3]));
+
+    adapter.command(input::Frame::Address(1));
+    assert_eq!(
+        adapter.command(input::Frame::Read),
+        output::Frame::Error(48)
+    );
+}
+
+#[test]
+fn read_after_seek_spans_checksummed_blocks() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        checksums: true,
+        ..Config::default()
+    });
+    let words: Vec<u16> = (0..IO_BUFFER_SIZE as u16).collect();
+    adapter.write_file(1, &words);
+
+    adapter.seek(1, 3);
+    assert_eq!(adapter.command(input::Frame::Read), output::Frame::Ack);
+    for word in &words[3..] {
+        assert_eq!(
+            adapter.command(input::Frame::Data(0)),
+            output::Frame::Data(*word)
+        );
+    }
+    assert_eq!(adapter.command(input::Frame::Data(0)), output::Frame::End);
+}
+
+#[test]
+fn corrupted_block_is_reported_on_read_after_seek() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        checksums: true,
+        ..Config::default()
+    });
+    adapter.write_file(1, &[1, 2, 3]);
+    adapter.put_file("1", &words_to_bytes(&[1, 2, 246]));
+
+    adapter.seek(1, 1);
+    assert_eq!(
+        adapter.command(input::Frame::Read),
+        output::Frame::Error(48)
+    );
+}
+
+#[test]
+fn checksums_follow_backups() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        backups: 1,
+        checksums: true,
+        ..Config::default()
+    });
+    adapter.write_file(1, &[1]);
+    adapter.write_file(1, &[2]);
+    let checksum = |word: u16| Some(crc32(&word.to_le_bytes()).to_le_bytes().to_vec());
+    assert_eq!(adapter.file("1.CR1"), checksum(1));
+
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Restore);
+
+    assert_eq!(adapter.file("1.CRC"), checksum(1));
+    assert_eq!(adapter.file("1.CR1"), None);
+    assert_eq!(adapter.read_file(1, 1), [1]);
+}
+
+#[test]
+fn transfers_and_errors_are_logged_once_session_ends() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        log: true,
+        ..Config::default()
+    });
+    adapter.set_time(1_718_630_130);
+    adapter.detect_card(true);
+    adapter.write_file(1, &[1, 2, 3]);
+    assert_eq!(
+        String::from_utf8(adapter.file("ADAPTER.LOG").unwrap()).unwrap(),
+        "2024-06-17 13:15:30 boot\n\
+         2024-06-17 13:15:30 mount rejected=0\n\
+         2024-06-17 13:15:30 write address=1 words=3\n"
+    );
+
+    adapter.set_time(1_718_633_789);
+    adapter.read_file(1, 2);
+    adapter.command(input::Frame::Read);
+    adapter.command(input::Frame::Read);
+    adapter.send(input::Action::Reset);
+    let log = String::from_utf8(adapter.file("ADAPTER.LOG").unwrap()).unwrap();
+    assert!(log.ends_with(
+        "words=3\n\
+         2024-06-17 14:16:29 read address=1 words=2\n\
+         2024-06-17 14:16:29 error opcode=2\n"
+    ));
+    assert!(!adapter.is_storage_open());
+}
+
+#[test]
+fn log_is_not_written_unless_enabled() {
+    let mut adapter = Harness::new();
+    adapter.detect_card(true);
+    adapter.write_file(1, &[1]);
+
+    assert_eq!(adapter.file("ADAPTER.LOG"), None);
+}
+
+#[test]
+fn full_log_is_rotated() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        log: true,
+        ..Config::default()
+    });
+    let old = vec![b'x'; MAX_LOG_SIZE - 8];
+    adapter.put_file("ADAPTER.LOG", &old);
+    adapter.detect_card(true);
+
+    assert_eq!(adapter.file("ADAPTER.OLD"), Some(old));
+    assert_eq!(
+        adapter.file("ADAPTER.LOG").as_deref(),
+        Some(&b"1970-01-01 00:00:00 boot\n1970-01-01 00:00:00 mount rejected=0\n"[..])
+    );
+}
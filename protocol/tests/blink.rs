@@ -0,0 +1,93 @@
+use sm2m_protocol::{
+    blink::{Blinker, Leds},
+    Indicators,
+};
+
+/// Skips the boot pattern.
+fn booted() -> Blinker {
+    let mut blinker = Blinker::new();
+    while blinker.tick() != Leds::default() {}
+    blinker
+}
+
+fn error_levels(blinker: &mut Blinker, ticks: usize) -> String {
+    (0..ticks)
+        .map(|_| {
+            if blinker.tick().system_error {
+                '#'
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn leds_run_in_circle_after_boot() {
+    let mut blinker = Blinker::new();
+    let leds: Vec<Leds> = (0..3).map(|_| blinker.tick()).collect();
+
+    assert_eq!(
+        leds,
+        [
+            Leds {
+                write: true,
+                ..Leds::default()
+            },
+            Leds {
+                read: true,
+                ..Leds::default()
+            },
+            Leds {
+                system_error: true,
+                ..Leds::default()
+            },
+        ]
+    );
+}
+
+#[test]
+fn error_opcode_is_blinked_digit_by_digit() {
+    let mut blinker = booted();
+    blinker.error(20);
+
+    let digit_2 = "##...##........";
+    let digit_0 = "##...".repeat(10) + ".....";
+    let gap = ".".repeat(15);
+    let code = format!("{digit_2}{digit_0}{gap}");
+    assert_eq!(error_levels(&mut blinker, code.len() * 2), code.repeat(2));
+
+    blinker.system_error_off();
+    assert!(!blinker.tick().system_error);
+}
+
+#[test]
+fn missing_card_blinks_slowly() {
+    let mut blinker = booted();
+    blinker.card_missing();
+
+    let period = "#".repeat(10) + &".".repeat(10);
+    assert_eq!(error_levels(&mut blinker, 40), period.repeat(2));
+}
+
+#[test]
+fn transfer_led_flickers_with_transfer_rate() {
+    let mut blinker = booted();
+    blinker.write_on();
+    let mut dark_ticks = |words: usize| {
+        (0..20)
+            .filter(|_| {
+                blinker.transferred(words);
+                !blinker.tick().write
+            })
+            .count()
+    };
+
+    assert_eq!(dark_ticks(0), 0);
+    assert_eq!(dark_ticks(64), 5);
+    assert_eq!(dark_ticks(128), 10);
+    assert_eq!(dark_ticks(10_000), 10);
+
+    blinker.write_off();
+    assert!(!blinker.tick().write);
+}
@@ -0,0 +1,14 @@
+use sm2m_protocol::{checksum::crc32, storage::checksum_name};
+
+#[test]
+fn crc32_matches_reference_value() {
+    assert_eq!(crc32(b""), 0);
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+}
+
+#[test]
+fn checksum_file_follows_file_name() {
+    assert_eq!(checksum_name("12"), "12.CRC");
+    assert_eq!(checksum_name("12.TMP"), "12.CRT");
+    assert_eq!(checksum_name("12.BK3"), "12.CR3");
+}
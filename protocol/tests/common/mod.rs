@@ -0,0 +1,592 @@
+#![allow(dead_code)]
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::{BTreeMap, VecDeque},
+    rc::Rc,
+};
+
+use sm2m_protocol::{
+    adapter::IO_BUFFER_SIZE,
+    bus::{input, output, Input, Output, Parity},
+    error::Fault,
+    storage::Drive,
+    worker::{Buffer, Request, Spawn},
+    Config, Device, Indicators, Storage, Worker,
+};
+
+pub const FILE_NOT_FOUND: u16 = 27;
+pub const READ_ONLY: u16 = 40;
+pub const INVALID_FILE_OFFSET: u16 = 44;
+pub const TIMEOUT_READ_BUFFER: u16 = 6;
+
+#[derive(Clone, Default)]
+pub struct InputBus(Rc<RefCell<VecDeque<input::Action>>>);
+
+impl Input for InputBus {
+    fn read(&mut self) -> input::Action {
+        self.0.borrow_mut().pop_front().expect("no pending action")
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct OutputBus {
+    frames: Rc<RefCell<Vec<output::Frame>>>,
+    parity: Rc<Cell<bool>>,
+}
+
+impl Output for OutputBus {
+    fn write(&mut self, frame: output::Frame) {
+        self.frames.borrow_mut().push(frame);
+    }
+
+    fn set_parity(&mut self, enabled: bool) {
+        self.parity.set(enabled);
+    }
+}
+
+/// Storage requests spawned by the adapter and not processed by the worker yet.
+#[derive(Clone, Default)]
+pub struct Requests(Rc<RefCell<VecDeque<Request>>>);
+
+impl Spawn for Requests {
+    fn spawn(&mut self, request: Request) {
+        self.0.borrow_mut().push_back(request);
+    }
+}
+
+pub fn buffers() -> [&'static mut Buffer; 2] {
+    [
+        Box::leak(Box::new([0; IO_BUFFER_SIZE])),
+        Box::leak(Box::new([0; IO_BUFFER_SIZE])),
+    ]
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Leds {
+    pub system_error: bool,
+    pub write: bool,
+    pub read: bool,
+}
+
+#[derive(Clone, Default)]
+pub struct LedPanel(Rc<RefCell<Leds>>);
+
+impl Indicators for LedPanel {
+    fn system_error_on(&mut self) {
+        self.0.borrow_mut().system_error = true;
+    }
+
+    fn system_error_off(&mut self) {
+        self.0.borrow_mut().system_error = false;
+    }
+
+    fn write_on(&mut self) {
+        self.0.borrow_mut().write = true;
+    }
+
+    fn write_off(&mut self) {
+        self.0.borrow_mut().write = false;
+    }
+
+    fn read_on(&mut self) {
+        self.0.borrow_mut().read = true;
+    }
+
+    fn read_off(&mut self) {
+        self.0.borrow_mut().read = false;
+    }
+}
+
+#[derive(Default)]
+pub struct Card {
+    pub attached: bool,
+    pub read_only: bool,
+    pub open: bool,
+    pub time: Option<u32>,
+    /// SPI clock in kHz set by the adapter, 0 until it is set.
+    pub spi_clock: u32,
+    /// Number of times an inserted card was initialised.
+    pub mounts: usize,
+    /// Number of reads which time out before the card answers again.
+    pub read_timeouts: usize,
+    /// Number of in place writes which time out before the card answers again.
+    pub write_timeouts: usize,
+    pub drive: Drive,
+    /// Size of the card in bytes, the space not taken by files is free.
+    pub capacity: u64,
+    /// Files by path, `DRIVE<n>/<name>` in drive directories and `<partition>:<name>` in other
+    /// partitions.
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl Card {
+    fn path(&self, name: &str) -> String {
+        let mut path = String::new();
+        if self.drive.partition > 0 {
+            path += &format!("{}:", self.drive.partition);
+        }
+        if let Some(directory) = self.drive.directory {
+            path += &format!("DRIVE{directory}/");
+        }
+        path + name
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryStorage(Rc<RefCell<Card>>);
+
+impl MemoryStorage {
+    pub fn attached() -> Self {
+        let storage = Self::default();
+        storage.set_attached(true);
+        storage
+    }
+
+    pub fn set_attached(&self, attached: bool) {
+        self.0.borrow_mut().attached = attached;
+    }
+
+    pub fn file(&self, name: &str) -> Option<Vec<u8>> {
+        self.0.borrow().files.get(name).cloned()
+    }
+
+    pub fn put_file(&self, name: &str, data: &[u8]) {
+        self.0.borrow_mut().files.insert(name.into(), data.to_vec());
+    }
+}
+
+#[derive(Debug)]
+pub enum MemoryError {
+    FileNotFound,
+    ReadOnly,
+    InvalidFileOffset,
+    Timeout,
+}
+
+impl From<MemoryError> for u16 {
+    fn from(value: MemoryError) -> Self {
+        match value {
+            MemoryError::FileNotFound => FILE_NOT_FOUND,
+            MemoryError::ReadOnly => READ_ONLY,
+            MemoryError::InvalidFileOffset => INVALID_FILE_OFFSET,
+            MemoryError::Timeout => TIMEOUT_READ_BUFFER,
+        }
+    }
+}
+
+impl From<MemoryError> for Fault {
+    fn from(value: MemoryError) -> Self {
+        let retryable = matches!(value, MemoryError::Timeout);
+        Fault {
+            retryable,
+            ..Fault::new(value.into())
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    type Error = MemoryError;
+
+    fn is_attached(&mut self) -> bool {
+        self.0.borrow().attached
+    }
+
+    fn file_size(&mut self, name: &str) -> Result<Option<usize>, Self::Error> {
+        let mut card = self.0.borrow_mut();
+        card.open = true;
+        Ok(card.files.get(&card.path(name)).map(Vec::len))
+    }
+
+    fn remove_file(&mut self, name: &str) -> Result<(), Self::Error> {
+        let mut card = self.0.borrow_mut();
+        card.open = true;
+        let path = card.path(name);
+        card.files.remove(&path);
+        Ok(())
+    }
+
+    fn rename_file(&mut self, from: &str, to: &str) -> Result<bool, Self::Error> {
+        let mut card = self.0.borrow_mut();
+        card.open = true;
+        if card.read_only {
+            return Err(MemoryError::ReadOnly);
+        }
+        let (from, to) = (card.path(from), card.path(to));
+        match card.files.remove(&from) {
+            Some(data) => {
+                card.files.insert(to, data);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn read_file(
+        &mut self,
+        name: &str,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        let mut card = self.0.borrow_mut();
+        card.open = true;
+        if card.read_timeouts > 0 {
+            card.read_timeouts -= 1;
+            return Err(MemoryError::Timeout);
+        }
+        let file = card
+            .files
+            .get(&card.path(name))
+            .ok_or(MemoryError::FileNotFound)?;
+        let data = file.get(offset..).unwrap_or_default();
+        let size = data.len().min(buf.len());
+        buf[..size].copy_from_slice(&data[..size]);
+        Ok(size)
+    }
+
+    fn list_files(&mut self, visit: &mut dyn FnMut(&str, usize)) -> Result<(), Self::Error> {
+        let mut card = self.0.borrow_mut();
+        card.open = true;
+        let drive = card.path("");
+        for (path, data) in &card.files {
+            if let Some(name) = path.strip_prefix(&drive) {
+                if !name.contains(['/', ':']) {
+                    visit(name, data.len());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn total_space(&mut self) -> Result<u64, Self::Error> {
+        Ok(self.0.borrow().capacity)
+    }
+
+    fn free_space(&mut self) -> Result<u64, Self::Error> {
+        let card = self.0.borrow();
+        let used: usize = card.files.values().map(Vec::len).sum();
+        Ok(card.capacity.saturating_sub(used as u64))
+    }
+
+    fn append_file(&mut self, name: &str, buf: &[u8]) -> Result<usize, Self::Error> {
+        let mut card = self.0.borrow_mut();
+        card.open = true;
+        if card.read_only {
+            return Err(MemoryError::ReadOnly);
+        }
+        let path = card.path(name);
+        card.files.entry(path).or_default().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn write_file(&mut self, name: &str, offset: usize, buf: &[u8]) -> Result<usize, Self::Error> {
+        let mut card = self.0.borrow_mut();
+        card.open = true;
+        if card.read_only {
+            return Err(MemoryError::ReadOnly);
+        }
+        if card.write_timeouts > 0 {
+            card.write_timeouts -= 1;
+            return Err(MemoryError::Timeout);
+        }
+        let path = card.path(name);
+        let file = card.files.get_mut(&path).ok_or(MemoryError::FileNotFound)?;
+        if offset > file.len() {
+            return Err(MemoryError::InvalidFileOffset);
+        }
+        let end = offset + buf.len();
+        if end > file.len() {
+            file.resize(end, 0);
+        }
+        file[offset..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn set_time(&mut self, seconds: u32) -> Result<(), Self::Error> {
+        self.0.borrow_mut().time = Some(seconds);
+        Ok(())
+    }
+
+    fn now(&self) -> u32 {
+        self.0.borrow().time.unwrap_or(0)
+    }
+
+    fn set_spi_clock(&mut self, khz: u32) -> Result<(), Self::Error> {
+        let mut card = self.0.borrow_mut();
+        card.open = false;
+        card.spi_clock = khz;
+        Ok(())
+    }
+
+    fn attach(&mut self) -> Result<(), Self::Error> {
+        let mut card = self.0.borrow_mut();
+        card.open = false;
+        card.mounts += 1;
+        Ok(())
+    }
+
+    fn set_drive(&mut self, drive: Drive) -> Result<(), Self::Error> {
+        let mut card = self.0.borrow_mut();
+        if card.drive != drive {
+            card.open = false;
+            card.drive = drive;
+        }
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<(), Self::Error> {
+        self.0.borrow_mut().open = false;
+        Ok(())
+    }
+}
+
+pub struct Harness {
+    device: Device<InputBus, OutputBus, Requests, LedPanel>,
+    worker: Worker<MemoryStorage>,
+    input: InputBus,
+    output: OutputBus,
+    requests: Requests,
+    storage: MemoryStorage,
+    leds: LedPanel,
+    hold: bool,
+}
+
+impl Harness {
+    pub fn new() -> Self {
+        let input = InputBus::default();
+        let output = OutputBus::default();
+        let requests = Requests::default();
+        let storage = MemoryStorage::attached();
+        let leds = LedPanel::default();
+        let device = Device::new(
+            input.clone(),
+            output.clone(),
+            requests.clone(),
+            leds.clone(),
+            buffers(),
+        );
+
+        Self {
+            device,
+            worker: Worker::new(storage.clone()),
+            input,
+            output,
+            requests,
+            storage,
+            leds,
+            hold: false,
+        }
+    }
+
+    /// Triggers DTLI with the given action, lets the worker complete spawned requests unless it
+    /// is held and returns the last frame the adapter wrote.
+    pub fn send(&mut self, action: input::Action) -> output::Frame {
+        self.input.0.borrow_mut().push_back(action);
+        self.device.run();
+        if !self.hold {
+            while self.process() {}
+        }
+        self.last_frame()
+    }
+
+    /// Keeps spawned requests pending until released, as if the storage task was slow.
+    pub fn hold_worker(&mut self, hold: bool) {
+        self.hold = hold;
+    }
+
+    /// Completes the oldest pending request, returns `false` when there is none.
+    pub fn process(&mut self) -> bool {
+        let request = self.requests.0.borrow_mut().pop_front();
+        match request {
+            Some(request) => {
+                let response = self.worker.process(request);
+                self.device.complete(response);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn pending_requests(&self) -> usize {
+        self.requests.0.borrow().len()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.output.frames.borrow().len()
+    }
+
+    pub fn last_frame(&self) -> output::Frame {
+        *self
+            .output
+            .frames
+            .borrow()
+            .last()
+            .expect("adapter did not reply")
+    }
+
+    pub fn command(&mut self, frame: input::Frame) -> output::Frame {
+        let payload = frame.payload();
+        self.send(input::Action::Data(payload, Parity::of(payload)))
+    }
+
+    pub fn configure(&mut self, config: Config) {
+        self.device.configure(config);
+    }
+
+    pub fn config(&self) -> Config {
+        self.device.config()
+    }
+
+    /// Whether the output bus drives CTRLO_x parity.
+    pub fn output_parity(&self) -> bool {
+        self.output.parity.get()
+    }
+
+    pub fn write_file(&mut self, address: u16, words: &[u16]) {
+        assert_eq!(
+            self.command(input::Frame::Address(address)),
+            output::Frame::Ack
+        );
+        assert_eq!(self.command(input::Frame::Write), output::Frame::Ack);
+        for word in words {
+            assert_eq!(self.command(input::Frame::Data(*word)), output::Frame::Ack);
+        }
+        assert_eq!(self.send(input::Action::Stop), output::Frame::Ack);
+    }
+
+    pub fn read_file(&mut self, address: u16, count: usize) -> Vec<u16> {
+        assert_eq!(
+            self.command(input::Frame::Address(address)),
+            output::Frame::Ack
+        );
+        assert_eq!(self.command(input::Frame::Read), output::Frame::Ack);
+        let words = (0..count)
+            .map(|_| match self.command(input::Frame::Data(0)) {
+                output::Frame::Data(word) => word,
+                frame => panic!("unexpected frame {frame:?}"),
+            })
+            .collect();
+        assert_eq!(self.send(input::Action::Stop), output::Frame::Ack);
+        words
+    }
+
+    /// Selects the address and moves the start of the transfer to the word `offset`.
+    pub fn seek(&mut self, address: u16, offset: u32) {
+        assert_eq!(
+            self.command(input::Frame::Address(address)),
+            output::Frame::Ack
+        );
+        for frame in [
+            input::Frame::Seek,
+            input::Frame::Data((offset >> 16) as u16),
+            input::Frame::Data(offset as u16),
+        ] {
+            assert_eq!(self.command(frame), output::Frame::Ack);
+        }
+    }
+
+    pub fn select_drive(&mut self, number: u16) {
+        for frame in [input::Frame::Drive, input::Frame::Data(number)] {
+            assert_eq!(self.command(frame), output::Frame::Ack);
+        }
+    }
+
+    /// Sends the List or List sizes command and returns the words answered until DTEO.
+    pub fn list(&mut self, command: input::Frame) -> Vec<u16> {
+        assert_eq!(self.command(command), output::Frame::Ack);
+        let mut words = Vec::new();
+        loop {
+            match self.command(input::Frame::Data(0)) {
+                output::Frame::Data(word) => words.push(word),
+                output::Frame::End => break,
+                frame => panic!("unexpected frame {frame:?}"),
+            }
+        }
+        assert_eq!(self.send(input::Action::Stop), output::Frame::Ack);
+        words
+    }
+
+    /// Requests the error detail and returns the first `count` words of it.
+    pub fn error_detail(&mut self, count: usize) -> Vec<u16> {
+        assert_eq!(self.command(input::Frame::ErrorDetail), output::Frame::Ack);
+        (0..count)
+            .map(|_| match self.command(input::Frame::Data(0)) {
+                output::Frame::Data(word) => word,
+                frame => panic!("unexpected frame {frame:?}"),
+            })
+            .collect()
+    }
+
+    pub fn set_attached(&mut self, attached: bool) {
+        self.storage.0.borrow_mut().attached = attached;
+    }
+
+    /// Inserts or removes the card as reported by the detect pin.
+    pub fn detect_card(&mut self, attached: bool) {
+        self.set_attached(attached);
+        self.device.set_attached(attached);
+        if !self.hold {
+            while self.process() {}
+        }
+    }
+
+    /// Makes the next `count` reads time out.
+    pub fn fail_reads(&mut self, count: usize) {
+        self.storage.0.borrow_mut().read_timeouts = count;
+    }
+
+    /// Makes the next `count` in place writes time out.
+    pub fn fail_writes(&mut self, count: usize) {
+        self.storage.0.borrow_mut().write_timeouts = count;
+    }
+
+    pub fn set_capacity(&mut self, bytes: u64) {
+        self.storage.0.borrow_mut().capacity = bytes;
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.storage.0.borrow_mut().read_only = read_only;
+    }
+
+    pub fn file(&self, name: &str) -> Option<Vec<u8>> {
+        self.storage.0.borrow().files.get(name).cloned()
+    }
+
+    pub fn put_file(&mut self, name: &str, data: &[u8]) {
+        self.storage
+            .0
+            .borrow_mut()
+            .files
+            .insert(name.into(), data.to_vec());
+    }
+
+    /// Whether the storage holds a session opened by a file operation.
+    pub fn is_storage_open(&self) -> bool {
+        self.storage.0.borrow().open
+    }
+
+    pub fn mounts(&self) -> usize {
+        self.storage.0.borrow().mounts
+    }
+
+    pub fn time(&self) -> Option<u32> {
+        self.storage.0.borrow().time
+    }
+
+    /// Sets the storage clock as if it kept running from `seconds`.
+    pub fn set_time(&mut self, seconds: u32) {
+        self.storage.0.borrow_mut().time = Some(seconds);
+    }
+
+    pub fn spi_clock(&self) -> u32 {
+        self.storage.0.borrow().spi_clock
+    }
+
+    pub fn leds(&self) -> Leds {
+        *self.leds.0.borrow()
+    }
+}
+
+pub fn words_to_bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
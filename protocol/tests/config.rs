@@ -0,0 +1,64 @@
+use sm2m_protocol::{ByteOrder, Config, Drives, Layout};
+
+#[test]
+fn settings_are_applied() {
+    let mut config = Config::default();
+    let text = b"# Installation 2\r\n\r\nParity = on\r\nlayout=image\r\nsector_size = 4096\r\nbackups = 9\r\nchecksums = ON\r\nbyte_order = big\r\nlog = on\r\ndrives = Partitions\r\nreserve = 2048\r\nspi_clock = 12000\r\n";
+
+    assert_eq!(config.apply(text), 0);
+    assert_eq!(
+        config,
+        Config {
+            parity: true,
+            layout: Layout::Image { sector_size: 4096 },
+            backups: 9,
+            checksums: true,
+            byte_order: ByteOrder::Big,
+            log: true,
+            drives: Drives::Partitions,
+            reserve: 2048,
+            spi_clock: 12000,
+        }
+    );
+}
+
+#[test]
+fn invalid_lines_keep_previous_settings() {
+    let defaults = Config {
+        backups: 3,
+        checksums: true,
+        ..Config::default()
+    };
+    let mut config = defaults;
+    let text =
+        b"backups = 10\nchecksums = yes\nspeed = 1\nlayout\nbyte_order = middle\ndrives = 2\nreserve = -1\nspi_clock = 50\nspi_clock = 25001\n";
+
+    assert_eq!(config.apply(text), 9);
+    assert_eq!(config, defaults);
+}
+
+#[test]
+fn sector_size_applies_to_image_layout_only() {
+    let mut config = Config::default();
+    assert_eq!(config.apply(b"sector_size = 512\nlayout = image"), 0);
+    assert_eq!(config.layout, Layout::Image { sector_size: 512 });
+
+    let mut config = Config::default();
+    assert_eq!(config.apply(b"layout = image\nsector_size = 511"), 1);
+    assert_eq!(config.layout, Layout::Image { sector_size: 65536 });
+
+    let mut config = Config::default();
+    assert_eq!(config.apply(b"sector_size = 512"), 0);
+    assert_eq!(config.layout, Layout::Files);
+}
+
+#[test]
+fn sector_size_keeps_every_sector_within_fat_file() {
+    let mut config = Config::default();
+    assert_eq!(config.apply(b"layout = image\nsector_size = 65538"), 1);
+    assert_eq!(config.layout, Layout::Image { sector_size: 65536 });
+
+    let mut config = Config::default();
+    assert_eq!(config.apply(b"layout = image\nsector_size = 4294967296"), 1);
+    assert_eq!(config.layout, Layout::Image { sector_size: 65536 });
+}
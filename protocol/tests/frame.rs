@@ -0,0 +1,73 @@
+use sm2m_protocol::bus::input::Frame;
+
+#[test]
+fn commands_are_decoded() {
+    assert_eq!(Frame::from(0x0000), Frame::CheckStatus);
+    assert_eq!(Frame::from(0x0001), Frame::Write);
+    assert_eq!(Frame::from(0x0002), Frame::Read);
+    assert_eq!(Frame::from(0x0004), Frame::SetTime);
+    assert_eq!(Frame::from(0x0005), Frame::Restore);
+    assert_eq!(Frame::from(0x0006), Frame::ErrorDetail);
+    assert_eq!(Frame::from(0x0008), Frame::AcknowledgeError);
+    assert_eq!(Frame::from(0x0009), Frame::Seek);
+    assert_eq!(Frame::from(0x000A), Frame::Append);
+    assert_eq!(Frame::from(0x000C), Frame::Exists);
+    assert_eq!(Frame::from(0x000D), Frame::Size);
+    assert_eq!(Frame::from(0x000E), Frame::LongAddress);
+    assert_eq!(Frame::from(0x0010), Frame::Drive);
+    assert_eq!(Frame::from(0x0011), Frame::List);
+    assert_eq!(Frame::from(0x0012), Frame::ListSizes);
+    assert_eq!(Frame::from(0x0014), Frame::Delete);
+    assert_eq!(Frame::from(0x0015), Frame::Move);
+    assert_eq!(Frame::from(0x0016), Frame::Capacity);
+    assert_eq!(Frame::from(0x0404), Frame::Data(0x0404));
+}
+
+#[test]
+fn address_is_carried_in_high_bits() {
+    assert_eq!(Frame::from(0x0003), Frame::Address(0));
+    assert_eq!(Frame::from(0x0403), Frame::Address(1));
+    assert_eq!(Frame::from(0xFFFF), Frame::Address(63));
+}
+
+#[test]
+fn commands_are_encoded() {
+    for frame in [
+        Frame::CheckStatus,
+        Frame::Write,
+        Frame::Read,
+        Frame::SetTime,
+        Frame::Restore,
+        Frame::ErrorDetail,
+        Frame::AcknowledgeError,
+        Frame::Seek,
+        Frame::Append,
+        Frame::Exists,
+        Frame::Size,
+        Frame::LongAddress,
+        Frame::Drive,
+        Frame::List,
+        Frame::ListSizes,
+        Frame::Delete,
+        Frame::Move,
+        Frame::Capacity,
+        Frame::Address(0),
+        Frame::Address(21),
+        Frame::Address(63),
+    ] {
+        assert_eq!(Frame::from(frame.payload()), frame);
+    }
+}
+
+#[test]
+fn parity_is_set_for_even_number_of_ones() {
+    use sm2m_protocol::bus::Parity;
+
+    // CTRLx_0 covers the high byte and CTRLx_1 the low one
+    let parity = |ctrl_0, ctrl_1| Parity { ctrl_0, ctrl_1 };
+    assert_eq!(Parity::of(0x0000), parity(true, true));
+    assert_eq!(Parity::of(0x0001), parity(true, false));
+    assert_eq!(Parity::of(0x0100), parity(false, true));
+    assert_eq!(Parity::of(0x0703), parity(false, true));
+    assert_eq!(Parity::of(0xFFFF), parity(true, true));
+}
@@ -0,0 +1,147 @@
+mod common;
+
+use common::{words_to_bytes, Harness};
+use sm2m_protocol::{
+    bus::{input, output},
+    storage::IMAGE_FILE_NAME,
+    Config, Layout,
+};
+
+const SECTOR_SIZE: usize = 16;
+const SECTORS: usize = 4;
+
+fn image_adapter() -> Harness {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        layout: Layout::Image {
+            sector_size: SECTOR_SIZE,
+        },
+        ..Config::default()
+    });
+    let image: Vec<u8> = (0..(SECTOR_SIZE * SECTORS) as u8).collect();
+    adapter.put_file(IMAGE_FILE_NAME, &image);
+    adapter
+}
+
+#[test]
+fn write_overwrites_sector_in_place() {
+    let mut adapter = image_adapter();
+    let before = adapter.file(IMAGE_FILE_NAME).unwrap();
+
+    adapter.write_file(2, &[0xAAAA, 0xBBBB]);
+
+    let mut expected = before;
+    expected[2 * SECTOR_SIZE..2 * SECTOR_SIZE + 4].copy_from_slice(&[0xAA, 0xAA, 0xBB, 0xBB]);
+    assert_eq!(adapter.file(IMAGE_FILE_NAME), Some(expected));
+    assert_eq!(adapter.file("2"), None);
+}
+
+#[test]
+fn read_starts_at_sector() {
+    let mut adapter = image_adapter();
+
+    let words = adapter.read_file(1, 2);
+
+    assert_eq!(words_to_bytes(&words), [16, 17, 18, 19]);
+}
+
+#[test]
+fn written_sector_is_read_back() {
+    let mut adapter = image_adapter();
+    let words = [1, 2, 3, 4, 5, 6, 7, 8];
+
+    adapter.write_file(3, &words);
+
+    assert_eq!(adapter.read_file(3, words.len()), words);
+    assert_eq!(
+        adapter.file(IMAGE_FILE_NAME).unwrap().len(),
+        SECTOR_SIZE * SECTORS
+    );
+}
+
+#[test]
+fn read_ends_at_sector_end() {
+    let mut adapter = image_adapter();
+
+    let words = adapter.read_file(1, SECTOR_SIZE / 2);
+    assert_eq!(words_to_bytes(&words), (16..32).collect::<Vec<u8>>());
+
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Read);
+    for _ in 0..SECTOR_SIZE / 2 {
+        adapter.command(input::Frame::Data(0));
+    }
+    assert_eq!(adapter.command(input::Frame::Data(0)), output::Frame::End);
+}
+
+#[test]
+fn write_stops_at_sector_end() {
+    let mut adapter = image_adapter();
+    let before = adapter.file(IMAGE_FILE_NAME).unwrap();
+
+    adapter.write_file(1, &[0xAAAA; SECTOR_SIZE / 2 + 2]);
+
+    let mut expected = before;
+    expected[SECTOR_SIZE..2 * SECTOR_SIZE].fill(0xAA);
+    assert_eq!(adapter.file(IMAGE_FILE_NAME), Some(expected));
+}
+
+#[test]
+fn seek_stops_at_sector_end() {
+    let mut adapter = image_adapter();
+
+    adapter.seek(1, SECTOR_SIZE as u32);
+    assert_eq!(adapter.command(input::Frame::Read), output::Frame::Ack);
+    assert_eq!(adapter.command(input::Frame::Data(0)), output::Frame::End);
+}
+
+#[test]
+fn missing_image_is_reported() {
+    let mut adapter = Harness::new();
+    adapter.configure(Config {
+        layout: Layout::Image {
+            sector_size: SECTOR_SIZE,
+        },
+        ..Config::default()
+    });
+
+    adapter.command(input::Frame::Address(0));
+    assert_eq!(
+        adapter.command(input::Frame::Write),
+        output::Frame::Error(50)
+    );
+}
+
+#[test]
+fn sector_beyond_image_is_rejected() {
+    let mut adapter = image_adapter();
+
+    adapter.command(input::Frame::Address(SECTORS as u16 + 1));
+    assert_eq!(
+        adapter.command(input::Frame::Write),
+        output::Frame::Error(52)
+    );
+}
+
+#[test]
+fn queries_are_answered_for_sector() {
+    let mut adapter = image_adapter();
+
+    adapter.command(input::Frame::Address(3));
+    assert_eq!(
+        adapter.command(input::Frame::Exists),
+        output::Frame::Data(1)
+    );
+    assert_eq!(adapter.command(input::Frame::Size), output::Frame::Data(0));
+    assert_eq!(
+        adapter.command(input::Frame::Data(0)),
+        output::Frame::Data(SECTOR_SIZE as u16 / 2)
+    );
+    adapter.send(input::Action::Stop);
+
+    adapter.command(input::Frame::Address(SECTORS as u16));
+    assert_eq!(
+        adapter.command(input::Frame::Exists),
+        output::Frame::Data(0)
+    );
+}
@@ -0,0 +1,257 @@
+mod common;
+
+use common::{buffers, words_to_bytes, LedPanel, MemoryStorage, FILE_NOT_FOUND};
+use sm2m_protocol::{
+    bus::Parity,
+    emulator::{Event, Machine},
+    virtual_bus::{Signal, VirtualBus},
+    Config, Device, Worker,
+};
+
+const TRANSFERS: usize = 100;
+
+fn sequence(count: usize) -> Vec<u16> {
+    (0..count as u16).collect()
+}
+
+#[test]
+fn write_session_stores_word_sequence() {
+    let bus = VirtualBus::new();
+    let storage = MemoryStorage::attached();
+    let mut adapter = Device::new(
+        bus.adapter(),
+        bus.adapter(),
+        bus.storage(),
+        LedPanel::default(),
+        buffers(),
+    );
+    let mut worker = Worker::new(storage.clone());
+    let mut emulator = Machine::new(bus.emulator(), bus.emulator(), TRANSFERS);
+
+    emulator.start_write();
+
+    assert_eq!(
+        bus.run(&mut adapter, &mut worker, &mut emulator),
+        Some(Signal::Event(Event::WriteCompleted))
+    );
+    assert!(emulator.is_completed());
+    assert_eq!(
+        storage.file("1"),
+        Some(words_to_bytes(&sequence(TRANSFERS)))
+    );
+}
+
+#[test]
+fn read_session_verifies_written_sequence() {
+    let bus = VirtualBus::new();
+    let storage = MemoryStorage::attached();
+    let mut adapter = Device::new(
+        bus.adapter(),
+        bus.adapter(),
+        bus.storage(),
+        LedPanel::default(),
+        buffers(),
+    );
+    let mut worker = Worker::new(storage);
+    let mut emulator = Machine::new(bus.emulator(), bus.emulator(), TRANSFERS);
+
+    emulator.start_write();
+    bus.run(&mut adapter, &mut worker, &mut emulator);
+    emulator.stop();
+    bus.run(&mut adapter, &mut worker, &mut emulator);
+    emulator.start_read();
+
+    assert_eq!(
+        bus.run(&mut adapter, &mut worker, &mut emulator),
+        Some(Signal::Event(Event::ReadCompleted {
+            last_received: TRANSFERS as u16 - 1
+        }))
+    );
+}
+
+#[test]
+fn read_past_end_of_file_raises_dteo() {
+    let bus = VirtualBus::new();
+    let mut adapter = Device::new(
+        bus.adapter(),
+        bus.adapter(),
+        bus.storage(),
+        LedPanel::default(),
+        buffers(),
+    );
+    let mut worker = Worker::new(MemoryStorage::attached());
+    let mut emulator = Machine::new(bus.emulator(), bus.emulator(), TRANSFERS);
+
+    emulator.start_write();
+    bus.run(&mut adapter, &mut worker, &mut emulator);
+    emulator.stop();
+    bus.run(&mut adapter, &mut worker, &mut emulator);
+    emulator.set_transfers(TRANSFERS + 1);
+    emulator.start_read();
+
+    assert_eq!(
+        bus.run(&mut adapter, &mut worker, &mut emulator),
+        Some(Signal::Event(Event::ReadEnded {
+            last_received: TRANSFERS as u16 - 1
+        }))
+    );
+    assert!(!bus.lines().dteo);
+}
+
+#[test]
+fn read_session_detects_unexpected_data() {
+    let bus = VirtualBus::new();
+    let storage = MemoryStorage::attached();
+    let mut words = sequence(TRANSFERS);
+    words[50] = 0xDEAD;
+    storage.put_file("1", &words_to_bytes(&words));
+    let mut adapter = Device::new(
+        bus.adapter(),
+        bus.adapter(),
+        bus.storage(),
+        LedPanel::default(),
+        buffers(),
+    );
+    let mut worker = Worker::new(storage);
+    let mut emulator = Machine::new(bus.emulator(), bus.emulator(), TRANSFERS);
+
+    emulator.start_read();
+
+    assert_eq!(
+        bus.run(&mut adapter, &mut worker, &mut emulator),
+        Some(Signal::Event(Event::InvalidData {
+            expected: 50,
+            received: 0xDEAD,
+            last_received: 49,
+        }))
+    );
+}
+
+#[test]
+fn read_session_of_missing_file_raises_erro() {
+    let bus = VirtualBus::new();
+    let mut adapter = Device::new(
+        bus.adapter(),
+        bus.adapter(),
+        bus.storage(),
+        LedPanel::default(),
+        buffers(),
+    );
+    let mut worker = Worker::new(MemoryStorage::attached());
+    let mut emulator = Machine::new(bus.emulator(), bus.emulator(), TRANSFERS);
+
+    emulator.start_read();
+
+    assert_eq!(
+        bus.run(&mut adapter, &mut worker, &mut emulator),
+        Some(Signal::Error(FILE_NOT_FOUND))
+    );
+    assert!(!bus.lines().erro);
+    assert!(bus.lines().rdy);
+}
+
+#[test]
+fn detached_card_stops_session_at_check_status() {
+    let bus = VirtualBus::new();
+    let mut adapter = Device::new(
+        bus.adapter(),
+        bus.adapter(),
+        bus.storage(),
+        LedPanel::default(),
+        buffers(),
+    );
+    let mut worker = Worker::new(MemoryStorage::default());
+    let mut emulator = Machine::new(bus.emulator(), bus.emulator(), TRANSFERS);
+
+    emulator.start_write();
+
+    assert_eq!(
+        bus.run(&mut adapter, &mut worker, &mut emulator),
+        Some(Signal::Error(1))
+    );
+}
+
+#[test]
+fn lines_are_active_low() {
+    let bus = VirtualBus::new();
+    let mut adapter = Device::new(
+        bus.adapter(),
+        bus.adapter(),
+        bus.storage(),
+        LedPanel::default(),
+        buffers(),
+    );
+    let mut worker = Worker::new(MemoryStorage::attached());
+    let mut emulator = Machine::new(bus.emulator(), bus.emulator(), TRANSFERS);
+
+    emulator.start_write();
+    let lines = bus.lines();
+    assert!(!lines.rsti);
+    assert!(!lines.dtli);
+    assert!(lines.dtei);
+    assert_eq!(lines.di, u16::MAX);
+
+    adapter.run();
+    bus.process(&mut adapter, &mut worker);
+    let lines = bus.lines();
+    assert!(!lines.rdy);
+    assert!(lines.erro);
+    assert_eq!(lines.do_, u16::MAX);
+
+    emulator.step();
+    assert!(bus.lines().rsti);
+    assert_eq!(bus.lines().di, u16::MAX); // check status command is all zeros
+
+    adapter.run();
+    bus.process(&mut adapter, &mut worker);
+    emulator.step();
+    assert_eq!(bus.lines().di, !0x0403); // address 1 command
+}
+
+#[test]
+fn parity_is_driven_once_enabled() {
+    let bus = VirtualBus::new();
+    let storage = MemoryStorage::attached();
+    let mut adapter = Device::new(
+        bus.adapter(),
+        bus.adapter(),
+        bus.storage(),
+        LedPanel::default(),
+        buffers(),
+    );
+    let mut worker = Worker::new(storage.clone());
+    let mut emulator = Machine::new(bus.emulator(), bus.emulator(), TRANSFERS);
+
+    emulator.start_write();
+    bus.run(&mut adapter, &mut worker, &mut emulator);
+    let lines = bus.lines();
+    assert!(lines.ctrlo_0 && lines.ctrlo_1 && lines.ctrl_d);
+
+    adapter.configure(Config {
+        parity: true,
+        ..Config::default()
+    });
+    emulator.stop();
+    bus.run(&mut adapter, &mut worker, &mut emulator);
+    emulator.start_read();
+    assert_eq!(
+        bus.run(&mut adapter, &mut worker, &mut emulator),
+        Some(Signal::Event(Event::ReadCompleted {
+            last_received: TRANSFERS as u16 - 1
+        }))
+    );
+
+    storage.set_attached(false);
+    emulator.stop();
+    bus.run(&mut adapter, &mut worker, &mut emulator);
+    emulator.start_write();
+    assert_eq!(
+        bus.run(&mut adapter, &mut worker, &mut emulator),
+        Some(Signal::Error(1))
+    );
+    let lines = bus.lines();
+    let parity = Parity::of(1);
+    assert!(!lines.ctrl_d);
+    assert_eq!(lines.ctrlo_0, !parity.ctrl_0);
+    assert_eq!(lines.ctrlo_1, !parity.ctrl_1);
+}
@@ -0,0 +1,119 @@
+mod common;
+
+use common::{words_to_bytes, Harness, READ_ONLY};
+use sm2m_protocol::{
+    adapter::IO_BUFFER_SIZE,
+    bus::{input, output},
+};
+
+const BUFFER_WORDS: u16 = IO_BUFFER_SIZE as u16 / 2;
+
+#[test]
+fn words_are_acknowledged_while_full_buffer_is_written() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Write);
+    for word in 0..BUFFER_WORDS {
+        adapter.command(input::Frame::Data(word));
+    }
+
+    adapter.hold_worker(true);
+    for word in BUFFER_WORDS..BUFFER_WORDS * 2 {
+        assert_eq!(
+            adapter.command(input::Frame::Data(word)),
+            output::Frame::Ack
+        );
+    }
+    assert_eq!(adapter.pending_requests(), 1);
+    assert_eq!(adapter.file("1.TMP"), None);
+
+    adapter.process();
+    let words: Vec<u16> = (0..BUFFER_WORDS).collect();
+    assert_eq!(adapter.file("1.TMP"), Some(words_to_bytes(&words)));
+}
+
+#[test]
+fn word_waits_until_both_buffers_are_free() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Write);
+    adapter.hold_worker(true);
+    for word in 0..BUFFER_WORDS * 2 {
+        adapter.command(input::Frame::Data(word));
+    }
+
+    let frames = adapter.frame_count();
+    adapter.command(input::Frame::Data(0));
+    assert_eq!(adapter.frame_count(), frames);
+
+    adapter.process();
+    assert_eq!(adapter.frame_count(), frames + 1);
+    assert_eq!(adapter.last_frame(), output::Frame::Ack);
+    assert_eq!(adapter.pending_requests(), 1);
+}
+
+#[test]
+fn stop_is_acknowledged_once_data_is_written() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Write);
+    adapter.command(input::Frame::Data(0x0102));
+    adapter.hold_worker(true);
+
+    let frames = adapter.frame_count();
+    adapter.send(input::Action::Stop);
+    assert_eq!(adapter.frame_count(), frames);
+
+    while adapter.process() {}
+    assert_eq!(adapter.frame_count(), frames + 1);
+    assert_eq!(adapter.last_frame(), output::Frame::Ack);
+    assert_eq!(adapter.file("1"), Some(vec![0x02, 0x01]));
+    assert!(!adapter.is_storage_open());
+}
+
+#[test]
+fn next_buffer_is_read_ahead() {
+    let mut adapter = Harness::new();
+    let words: Vec<u16> = (0..BUFFER_WORDS * 2).collect();
+    adapter.put_file("1", &words_to_bytes(&words));
+    adapter.command(input::Frame::Address(1));
+    adapter.hold_worker(true);
+
+    adapter.command(input::Frame::Read);
+    adapter.process();
+    assert_eq!(adapter.last_frame(), output::Frame::Ack);
+    assert_eq!(adapter.pending_requests(), 1);
+
+    for word in 0..BUFFER_WORDS {
+        assert_eq!(
+            adapter.command(input::Frame::Data(0)),
+            output::Frame::Data(word)
+        );
+    }
+    let frames = adapter.frame_count();
+    adapter.command(input::Frame::Data(0));
+    assert_eq!(adapter.frame_count(), frames);
+
+    adapter.process();
+    assert_eq!(adapter.last_frame(), output::Frame::Data(BUFFER_WORDS));
+}
+
+#[test]
+fn write_behind_error_is_reported_in_reply_to_next_word() {
+    let mut adapter = Harness::new();
+    adapter.command(input::Frame::Address(1));
+    adapter.command(input::Frame::Write);
+    for word in 0..BUFFER_WORDS {
+        adapter.command(input::Frame::Data(word));
+    }
+    adapter.hold_worker(true);
+    adapter.command(input::Frame::Data(0));
+    adapter.set_read_only(true);
+
+    adapter.process();
+    assert_eq!(
+        adapter.command(input::Frame::Data(0)),
+        output::Frame::Error(READ_ONLY)
+    );
+    assert!(adapter.leds().system_error);
+}
@@ -0,0 +1,504 @@
+use core::ptr::NonNull;
+
+use embedded_sdmmc::{filesystem::FileError, Block, TimeSource};
+use sm2m_protocol::{
+    storage::{
+        address_of, checksum_name, drive_dir_name, temp_name, Drive, FileName, TEMP_EXTENSION,
+    },
+    Drives, Storage,
+};
+
+use crate::{
+    disk::Disk,
+    error::{ControllerError, StorageError},
+    time::{parse_time, Clock, ClockTimeSource, TIME_FILE_NAME},
+    Controller, SdMmcFile,
+};
+
+/// FAT storage mounted from a disk.
+///
+/// The volume is mounted by the first operation and stays mounted together with the last opened
+/// file until `Storage::close` is called, so a whole transfer shares a single card session.
+/// Files are stamped with the time of the clock.
+pub struct Card<D: Disk + 'static, C: Clock> {
+    session: Option<Session<D, ClockTimeSource<C>>>,
+    /// The disk, moved into the session while one is open.
+    disk: Option<&'static mut D>,
+    clock: C,
+    /// Drive the files are looked up in.
+    drive: Drive,
+}
+
+impl<D: Disk + 'static, C: Clock> Card<D, C> {
+    pub fn new(disk: &'static mut D, clock: C) -> Self {
+        Self {
+            session: None,
+            disk: Some(disk),
+            clock,
+            drive: Drive::default(),
+        }
+    }
+
+    pub fn free(mut self) -> &'static mut D {
+        self.drop_session();
+        self.disk.expect(DISK_HELD)
+    }
+
+    /// Sets the clock from `TIME.TXT` and removes the file, so the same time is not set again on
+    /// the next boot. Returns whether the clock was set, a file which can't be parsed is kept.
+    pub fn sync_clock(&mut self) -> Result<bool, StorageError> {
+        let result = self.read_time_file();
+        let closed = self.close();
+        let seconds = result?;
+        closed?;
+
+        if let Some(seconds) = seconds {
+            self.clock.set(seconds);
+            self.session()?.controller.delete_file(TIME_FILE_NAME)?;
+            self.close()?;
+        }
+        Ok(seconds.is_some())
+    }
+
+    /// Removes temporary files of write sessions which never ended with Stop from every drive
+    /// the card holds, the root of every partition and the `DRIVE<n>` directories of the first
+    /// one, and returns how many of them were removed.
+    pub fn discard_temp_files(&mut self) -> Result<usize, StorageError> {
+        let drive = self.drive;
+        let result = self.discard_temp_files_in_drives();
+        let closed = self.close();
+        self.drive = drive;
+        let count = result?;
+        closed?;
+        Ok(count)
+    }
+
+    fn discard_temp_files_in_drives(&mut self) -> Result<usize, StorageError> {
+        let mut count = self.discard_temp_files_in(Drive::default())?;
+
+        let mut directories = [false; u8::MAX as usize + 1];
+        self.session()?.controller.list_dirs(&mut |name| {
+            if let Some(number) = drive_dir_number(name) {
+                directories[number as usize] = true;
+            }
+        })?;
+        for number in (0..=u8::MAX).filter(|&number| directories[number as usize]) {
+            let drive = Drive {
+                partition: 0,
+                directory: Some(number),
+            };
+            count += self.discard_temp_files_in(drive)?;
+        }
+
+        for drive in (1..).map_while(|number| Drives::Partitions.drive(number)) {
+            match self.discard_temp_files_in(drive) {
+                Ok(removed) => count += removed,
+                // Unused entries of the partition table and partitions of other file systems
+                Err(StorageError::SdMmcController(
+                    ControllerError::FormatError(_) | ControllerError::NoSuchVolume,
+                )) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(count)
+    }
+
+    fn discard_temp_files_in(&mut self, drive: Drive) -> Result<usize, StorageError> {
+        self.set_drive(drive)?;
+        let result = self.remove_temp_files();
+        let closed = self.close();
+        let count = result?;
+        closed?;
+        Ok(count)
+    }
+
+    fn remove_temp_files(&mut self) -> Result<usize, StorageError> {
+        let controller = &mut self.session()?.controller;
+        let mut count = 0;
+        while let Some(name) = controller.find_file(&is_temp_file)? {
+            if !controller.delete_file(&name)? {
+                break;
+            }
+            if name.ends_with(TEMP_EXTENSION) {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    fn read_time_file(&mut self) -> Result<Option<u32>, StorageError> {
+        let session = self.session()?;
+        if !session.controller.is_file_exists(TIME_FILE_NAME)? {
+            return Ok(None);
+        }
+
+        let mut text = [0; 32];
+        let (controller, file) = session.file(TIME_FILE_NAME, Access::Read)?;
+        file.seek_from_start(0)?;
+        let size = controller.read(file, &mut text)?;
+        Ok(parse_time(&text[..size]))
+    }
+
+    fn session(&mut self) -> Result<&mut Session<D, ClockTimeSource<C>>, StorageError> {
+        let session = match self.session.take() {
+            Some(session) => session,
+            None => {
+                let disk = self.disk.take().expect(DISK_HELD);
+                Session::open(disk, self.clock.clone(), self.drive).map_err(|(disk, err)| {
+                    self.disk = Some(disk);
+                    err
+                })?
+            }
+        };
+
+        Ok(self.session.insert(session))
+    }
+
+    /// Drops the session without closing its files and takes the disk back.
+    fn drop_session(&mut self) {
+        if let Some(session) = self.session.take() {
+            self.disk = Some(session.release());
+        }
+    }
+}
+
+const DISK_HELD: &str = "the disk is held by the card while no session is open";
+
+/// Whether the file is the temporary file of an address or its checksums. Other `.TMP` files
+/// are left to their owners.
+fn is_temp_file(name: &str) -> bool {
+    let Some((base, _)) = name.split_once('.') else {
+        return false;
+    };
+    let temp = temp_name(base);
+    address_of(base).is_some() && (name == temp || name == checksum_name(&temp))
+}
+
+/// Number of the drive held by the `DRIVE<n>` directory.
+fn drive_dir_number(name: &str) -> Option<u8> {
+    let number = name.strip_prefix("DRIVE")?.parse().ok()?;
+    (drive_dir_name(number) == name).then_some(number)
+}
+
+/// Converts the offset to a position in a file, FAT files end before 4 GB.
+fn file_offset(offset: usize) -> Result<u32, StorageError> {
+    u32::try_from(offset).map_err(|_| StorageError::SdMmcFile(FileError::InvalidOffset))
+}
+
+fn mount<D: Disk, C: Clock>(
+    disk: &mut D,
+    clock: C,
+    drive: Drive,
+) -> Result<Controller<D::Device<'_>, ClockTimeSource<C>>, StorageError> {
+    let device = disk.acquire()?;
+    let mut ctl = embedded_sdmmc::Controller::new(device, ClockTimeSource(clock));
+    let vol = ctl.get_volume(embedded_sdmmc::VolumeIdx(drive.partition as usize))?;
+    let root = ctl.open_root_dir(&vol)?;
+    let dir = match drive.directory {
+        Some(number) => {
+            let dir = ctl.open_dir(&vol, &root, &drive_dir_name(number));
+            ctl.close_dir(&vol, root);
+            dir?
+        }
+        None => root,
+    };
+    Ok(Controller::new(ctl, vol, dir, drive.partition))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Append,
+    Write,
+}
+
+struct OpenFile {
+    name: FileName,
+    access: Access,
+    file: SdMmcFile,
+}
+
+/// Number of files kept open at once, enough for a data file and its checksums.
+const OPEN_FILES: usize = 2;
+
+struct Session<D: Disk + 'static, T: TimeSource> {
+    controller: Controller<D::Device<'static>, T>,
+    /// Open files, the most recently used one first.
+    files: [Option<OpenFile>; OPEN_FILES],
+    /// Disk the device of the controller is acquired from.
+    ///
+    /// The `&'static mut D` the session is opened with, kept as a pointer because the device
+    /// borrows the disk for as long as the session lives. It is turned back into the reference
+    /// only once the controller and with it the device is gone.
+    disk: NonNull<D>,
+}
+
+// SAFETY: `disk` comes from a `&'static mut D` which nothing else holds, so the session owns the
+// disk exactly like that reference would, and `&mut D` is `Send` when `D` is. Everything else the
+// session holds is required to be `Send` itself.
+unsafe impl<D, T> Send for Session<D, T>
+where
+    D: Disk + Send + 'static,
+    D::Device<'static>: Send,
+    T: TimeSource + Send,
+{
+}
+
+impl<D: Disk + 'static, C: Clock> Session<D, ClockTimeSource<C>> {
+    /// Mounts the volume of the drive, the disk is handed back if it can't be mounted.
+    fn open(
+        disk: &'static mut D,
+        clock: C,
+        drive: Drive,
+    ) -> Result<Self, (&'static mut D, StorageError)> {
+        let mut ptr = NonNull::from(disk);
+        // SAFETY: the pointer comes from the unique `&'static mut D` moved in above, so the
+        // device may borrow the disk for `'static`. The session never dereferences `disk` while
+        // the device lives, `release` and `close` drop the device before they give the disk back.
+        match mount(unsafe { ptr.as_mut() }, clock, drive) {
+            Ok(controller) => Ok(Self {
+                controller,
+                files: Default::default(),
+                disk: ptr,
+            }),
+            // SAFETY: a failed mount drops the device before returning, the error borrows
+            // nothing, so the disk is not borrowed anymore.
+            Err(err) => Err((unsafe { ptr.as_mut() }, err)),
+        }
+    }
+}
+
+impl<D: Disk + 'static, T: TimeSource> Session<D, T> {
+    /// Returns the open file, opening it if it is not open with the same access yet. The least
+    /// recently used file is closed to make room for it.
+    fn file(
+        &mut self,
+        name: &str,
+        access: Access,
+    ) -> Result<(&mut Controller<D::Device<'static>, T>, &mut SdMmcFile), StorageError> {
+        let pos = self.files.iter().position(|open| {
+            open.as_ref()
+                .is_some_and(|open| open.name == name && open.access == access)
+        });
+        let open = match pos.and_then(|pos| self.files[pos].take()) {
+            Some(open) => open,
+            None => {
+                self.close_file(name)?;
+                let file = match access {
+                    Access::Read => self.controller.open_file_read(name)?,
+                    Access::Append => self.controller.oped_file_append(name)?,
+                    Access::Write => self.controller.open_file_write(name)?,
+                };
+                let name = FileName::from(name);
+                OpenFile { name, access, file }
+            }
+        };
+
+        if self.files[0].is_some() {
+            if let Some(evicted) = self.files[OPEN_FILES - 1].take() {
+                self.controller.close_file(evicted.file)?;
+            }
+            self.files.rotate_right(1);
+        }
+        Ok((&mut self.controller, &mut self.files[0].insert(open).file))
+    }
+
+    /// Closes the file if it is open.
+    fn close_file(&mut self, name: &str) -> Result<(), StorageError> {
+        for slot in self.files.iter_mut() {
+            if let Some(open) = slot.take_if(|open| open.name == name) {
+                self.controller.close_file(open.file)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn close_files(&mut self) -> Result<(), StorageError> {
+        let mut result = Ok(());
+        for open in self.files.iter_mut().filter_map(Option::take) {
+            result = result.and(self.controller.close_file(open.file));
+        }
+        result
+    }
+
+    /// Closes the files and the volume and gives the disk back.
+    fn close(mut self) -> (&'static mut D, Result<(), StorageError>) {
+        let result = self.close_files();
+        let mut disk = self.disk;
+        self.controller.close();
+        // SAFETY: closing the controller dropped the device, the only borrow of the disk.
+        (unsafe { disk.as_mut() }, result)
+    }
+
+    /// Gives the disk back leaving the files and the volume as they are.
+    fn release(self) -> &'static mut D {
+        let mut disk = self.disk;
+        drop(self.controller);
+        // SAFETY: dropping the controller dropped the device, the only borrow of the disk.
+        unsafe { disk.as_mut() }
+    }
+}
+
+impl<D: Disk + 'static, C: Clock> Storage for Card<D, C> {
+    type Error = StorageError;
+
+    fn is_attached(&mut self) -> bool {
+        let Some(disk) = self.disk.as_deref_mut() else {
+            // The card is in use by the open session
+            return true;
+        };
+
+        match mount(disk, self.clock.clone(), Drive::default()) {
+            Ok(controller) => {
+                controller.close();
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn file_size(&mut self, name: &str) -> Result<Option<usize>, StorageError> {
+        let session = self.session()?;
+        // The directory entry is updated once the file is closed
+        session.close_file(name)?;
+        let size = session.controller.file_size(name)?;
+        Ok(size.map(|size| size as usize))
+    }
+
+    fn remove_file(&mut self, name: &str) -> Result<(), StorageError> {
+        let session = self.session()?;
+        session.close_file(name)?;
+        if session.controller.is_file_exists(name)? {
+            session.controller.delete_file(name)?;
+        }
+        Ok(())
+    }
+
+    fn rename_file(&mut self, from: &str, to: &str) -> Result<bool, StorageError> {
+        let session = self.session()?;
+        session.close_file(from)?;
+        session.close_file(to)?;
+        if !session.controller.is_file_exists(from)? {
+            return Ok(false);
+        }
+
+        session.controller.delete_file(to)?;
+        session.controller.rename_file(from, to)?;
+        Ok(true)
+    }
+
+    fn read_file(
+        &mut self,
+        name: &str,
+        offset: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, StorageError> {
+        let (controller, file) = self.session()?.file(name, Access::Read)?;
+        let offset = file_offset(offset)?;
+        if offset > file.length() {
+            return Ok(0);
+        }
+        file.seek_from_start(offset)?;
+        controller.read(file, buf)
+    }
+
+    fn list_files(&mut self, visit: &mut dyn FnMut(&str, usize)) -> Result<(), StorageError> {
+        let session = self.session()?;
+        // Directory entries of open files are updated once they are closed
+        session.close_files()?;
+        session.controller.list_files(visit)
+    }
+
+    fn total_space(&mut self) -> Result<u64, StorageError> {
+        let partition = self.drive.partition;
+        self.session()?.controller.total_space(partition)
+    }
+
+    fn free_space(&mut self) -> Result<u64, StorageError> {
+        let partition = self.drive.partition;
+        self.session()?.controller.free_space(partition)
+    }
+
+    fn append_file(&mut self, name: &str, buf: &[u8]) -> Result<usize, StorageError> {
+        let (controller, file) = self.session()?.file(name, Access::Append)?;
+        controller.write(file, buf)
+    }
+
+    fn write_file(&mut self, name: &str, offset: usize, buf: &[u8]) -> Result<usize, StorageError> {
+        let session = self.session()?;
+        let (controller, file) = session.file(name, Access::Write)?;
+
+        // The controller reads a block back only when a write starts in the middle of it, so the
+        // rest of the last block has to be written again to keep the data which follows.
+        let start = file_offset(offset)?;
+        let end = file_offset(offset.saturating_add(buf.len()))?;
+        let mut tail = [0; Block::LEN];
+        let tail_len = (Block::LEN_U32 - end % Block::LEN_U32) % Block::LEN_U32;
+        let tail_len = tail_len.min(file.length().saturating_sub(end)) as usize;
+        let length = file.length().max(end);
+        if tail_len > 0 {
+            file.seek_from_start(end)?;
+            controller.read(file, &mut tail[..tail_len])?;
+        }
+
+        file.seek_from_start(start)?;
+        let size = controller.write(file, buf)?;
+        if tail_len > 0 {
+            controller.write(file, &tail[..tail_len])?;
+        }
+        if file.length() != length {
+            // The controller adds every written byte to the size, the overwritten ones as well
+            session.close_file(name)?;
+            session.controller.set_file_size(name, length)?;
+        }
+        Ok(size)
+    }
+
+    fn set_time(&mut self, seconds: u32) -> Result<(), StorageError> {
+        self.clock.set(seconds);
+        Ok(())
+    }
+
+    fn now(&self) -> u32 {
+        ClockTimeSource(self.clock.clone()).now()
+    }
+
+    fn set_spi_clock(&mut self, khz: u32) -> Result<(), StorageError> {
+        self.close()?;
+        self.disk
+            .as_deref_mut()
+            .expect(DISK_HELD)
+            .set_spi_clock(khz);
+        Ok(())
+    }
+
+    fn attach(&mut self) -> Result<(), StorageError> {
+        // Closing files of the removed card would write their directory entries to the new one
+        self.drop_session();
+        // The time file and the files of interrupted sessions are looked for in the default drive
+        let drive = core::mem::take(&mut self.drive);
+        let result = self.sync_clock().and_then(|_| self.discard_temp_files());
+        self.drive = drive;
+        result.map(|_| ())
+    }
+
+    fn set_drive(&mut self, drive: Drive) -> Result<(), StorageError> {
+        if self.drive == drive {
+            return Ok(());
+        }
+        let result = self.close();
+        self.drive = drive;
+        result
+    }
+
+    fn close(&mut self) -> Result<(), StorageError> {
+        match self.session.take() {
+            Some(session) => {
+                let (disk, result) = session.close();
+                self.disk = Some(disk);
+                result
+            }
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,467 @@
+use embedded_sdmmc::{
+    sdmmc::Error as SpiError, Block, BlockDevice, BlockIdx, FilenameError, ShortFileName,
+    TimeSource, VolumeIdx,
+};
+use sm2m_protocol::storage::FileName;
+
+use crate::{
+    error::{ControllerError, StorageError},
+    SdMmcController, SdMmcDirectory, SdMmcFile, SdMmcVolume,
+};
+
+/// Number of allocation table blocks read at once while free clusters are counted.
+const FAT_READ_BLOCKS: usize = 4;
+
+/// Clusters which hold the files of a FAT volume.
+struct Clusters {
+    /// First block of the allocation table.
+    fat_start: u32,
+    count: u32,
+    /// Size of a cluster in bytes.
+    size: u32,
+    /// Whether allocation table entries take 4 bytes instead of 2.
+    fat32: bool,
+    /// FSInfo sector of a FAT32 volume.
+    info_block: Option<u32>,
+}
+
+pub struct Controller<D: BlockDevice, T: TimeSource> {
+    ctl: SdMmcController<D, T>,
+    vol: SdMmcVolume,
+    dir: SdMmcDirectory,
+    /// Index of the partition the volume is mounted from.
+    partition: u8,
+}
+
+impl<D, T> Controller<D, T>
+where
+    D: BlockDevice<Error = SpiError>,
+    T: TimeSource,
+{
+    pub fn new(
+        ctl: SdMmcController<D, T>,
+        vol: SdMmcVolume,
+        dir: SdMmcDirectory,
+        partition: u8,
+    ) -> Self {
+        Self {
+            ctl,
+            vol,
+            dir,
+            partition,
+        }
+    }
+
+    pub fn close(mut self) {
+        self.ctl.close_dir(&self.vol, self.dir);
+    }
+
+    pub fn is_file_exists(&mut self, name: &str) -> Result<bool, StorageError> {
+        match self.ctl.find_directory_entry(&self.vol, &self.dir, name) {
+            Ok(_) => Ok(true),
+            Err(embedded_sdmmc::Error::FileNotFound) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn file_size(&mut self, name: &str) -> Result<Option<u32>, StorageError> {
+        match self.ctl.find_directory_entry(&self.vol, &self.dir, name) {
+            Ok(entry) => Ok(Some(entry.size)),
+            Err(embedded_sdmmc::Error::FileNotFound) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn open_file_read(&mut self, name: &str) -> Result<SdMmcFile, StorageError> {
+        let file = self.ctl.open_file_in_dir(
+            &mut self.vol,
+            &self.dir,
+            name,
+            embedded_sdmmc::Mode::ReadOnly,
+        )?;
+
+        Ok(file)
+    }
+
+    pub fn oped_file_append(&mut self, name: &str) -> Result<SdMmcFile, StorageError> {
+        let file = self.ctl.open_file_in_dir(
+            &mut self.vol,
+            &self.dir,
+            name,
+            embedded_sdmmc::Mode::ReadWriteCreateOrAppend,
+        )?;
+
+        Ok(file)
+    }
+
+    pub fn open_file_write(&mut self, name: &str) -> Result<SdMmcFile, StorageError> {
+        let file = self.ctl.open_file_in_dir(
+            &mut self.vol,
+            &self.dir,
+            name,
+            embedded_sdmmc::Mode::ReadWriteAppend,
+        )?;
+
+        Ok(file)
+    }
+
+    pub fn close_file(&mut self, file: SdMmcFile) -> Result<(), StorageError> {
+        self.ctl.close_file(&self.vol, file)?;
+        Ok(())
+    }
+
+    /// Sets the size in the directory entry of the file, the file must not be open.
+    pub fn set_file_size(&mut self, name: &str, size: u32) -> Result<(), StorageError> {
+        let entry = self.ctl.find_directory_entry(&self.vol, &self.dir, name)?;
+        let mut blocks = [Block::new()];
+        let device = self.ctl.device();
+        device.read(&mut blocks, entry.entry_block, "size")?;
+        // The size is the last field of the 32 byte entry
+        let offset = entry.entry_offset as usize + 28;
+        blocks[0].contents[offset..offset + 4].copy_from_slice(&size.to_le_bytes());
+        device.write(&blocks, entry.entry_block)?;
+        Ok(())
+    }
+
+    /// Deletes the file and frees its clusters, the file must not be open.
+    pub fn delete_file(&mut self, name: &str) -> Result<bool, StorageError> {
+        let entry = match self.ctl.find_directory_entry(&self.vol, &self.dir, name) {
+            Ok(entry) => entry,
+            Err(embedded_sdmmc::Error::FileNotFound) => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+        if entry.attributes.is_directory() {
+            return Err(embedded_sdmmc::Error::DeleteDirAsFile.into());
+        }
+
+        // embedded-sdmmc only marks the directory entry as deleted
+        let mut blocks = [Block::new()];
+        let device = self.ctl.device();
+        device.read(&mut blocks, entry.entry_block, "delete")?;
+        let offset = entry.entry_offset as usize;
+        let first_cluster = le_u16(&blocks[0].contents, offset + 20) << 16
+            | le_u16(&blocks[0].contents, offset + 26);
+        self.free_cluster_chain(first_cluster)?;
+
+        self.ctl.delete_file_in_dir(&self.vol, &self.dir, name)?;
+        Ok(true)
+    }
+
+    /// Marks the clusters of the chain starting at `first` as free. Only the first allocation
+    /// table is updated, the same as embedded-sdmmc does when it allocates clusters.
+    fn free_cluster_chain(&mut self, first: u32) -> Result<(), StorageError> {
+        let clusters = self.clusters(self.partition)?;
+        let entry_len = if clusters.fat32 { 4 } else { 2 };
+        let mut blocks = [Block::new()];
+        let mut loaded = None;
+        let mut freed = 0;
+        let mut cluster = first;
+        // Empty files have no cluster, the end of chain and bad cluster marks are past the count
+        while (2..clusters.count + 2).contains(&cluster) {
+            let offset = cluster * entry_len;
+            let block = BlockIdx(clusters.fat_start + offset / Block::LEN_U32);
+            if loaded != Some(block) {
+                let device = self.ctl.device();
+                if let Some(loaded) = loaded {
+                    device.write(&blocks, loaded)?;
+                }
+                device.read(&mut blocks, block, "fat")?;
+                loaded = Some(block);
+            }
+
+            let offset = (offset % Block::LEN_U32) as usize;
+            let entry = &mut blocks[0].contents[offset..offset + entry_len as usize];
+            cluster = if clusters.fat32 {
+                let value = le_u32(entry, 0);
+                // The upper 4 bits of FAT32 entries are reserved and have to be kept
+                entry.copy_from_slice(&(value & 0xF000_0000).to_le_bytes());
+                value & 0x0FFF_FFFF
+            } else {
+                let value = le_u16(entry, 0);
+                entry.fill(0);
+                value
+            };
+            freed += 1;
+        }
+        if let Some(loaded) = loaded {
+            self.ctl.device().write(&blocks, loaded)?;
+        }
+
+        let info_free = self.info_free_clusters(&clusters)?;
+        if let (Some(info_block), Some(free)) = (clusters.info_block, info_free) {
+            let info_block = BlockIdx(info_block);
+            let device = self.ctl.device();
+            device.read(&mut blocks, info_block, "fsinfo")?;
+            let free = (free + freed).min(clusters.count);
+            blocks[0].contents[488..492].copy_from_slice(&free.to_le_bytes());
+            device.write(&blocks, info_block)?;
+            // The mounted volume keeps its own number of free clusters and writes it to the
+            // FSInfo sector on the next write, so it is mounted again to read the new one
+            self.vol = self.ctl.get_volume(VolumeIdx(self.partition as usize))?;
+        }
+        Ok(())
+    }
+
+    /// Renames the file by rewriting the name in its directory entry, `to` must not exist.
+    pub fn rename_file(&mut self, from: &str, to: &str) -> Result<(), StorageError> {
+        let name = short_name(to)?;
+        let entry = self.ctl.find_directory_entry(&self.vol, &self.dir, from)?;
+        let mut blocks = [Block::new()];
+        let device = self.ctl.device();
+        device.read(&mut blocks, entry.entry_block, "rename")?;
+        let offset = entry.entry_offset as usize;
+        blocks[0].contents[offset..offset + name.len()].copy_from_slice(&name);
+        device.write(&blocks, entry.entry_block)?;
+        Ok(())
+    }
+
+    /// Returns the space for files on the volume of the partition, in bytes.
+    pub fn total_space(&mut self, partition: u8) -> Result<u64, StorageError> {
+        let clusters = self.clusters(partition)?;
+        Ok(clusters.count as u64 * clusters.size as u64)
+    }
+
+    /// Returns the space of the free clusters on the volume of the partition, in bytes.
+    ///
+    /// FAT32 volumes keep the number of free clusters in their FSInfo sector. Without a valid
+    /// number there, like on FAT16 volumes, the free clusters are counted in the allocation table.
+    pub fn free_space(&mut self, partition: u8) -> Result<u64, StorageError> {
+        let clusters = self.clusters(partition)?;
+        let free = match self.info_free_clusters(&clusters)? {
+            Some(free) => free as u64,
+            None => self.count_free_clusters(&clusters)?,
+        };
+        Ok(free * clusters.size as u64)
+    }
+
+    /// Returns the number of free clusters kept in the FSInfo sector, unless it is unknown or
+    /// can't be right.
+    fn info_free_clusters(&mut self, clusters: &Clusters) -> Result<Option<u32>, StorageError> {
+        let Some(info_block) = clusters.info_block else {
+            return Ok(None);
+        };
+        let mut blocks = [Block::new()];
+        self.ctl
+            .device()
+            .read(&mut blocks, BlockIdx(info_block), "fsinfo")?;
+        let info = &blocks[0].contents;
+        let signed = le_u32(info, 0) == 0x4161_5252 && le_u32(info, 484) == 0x6141_7272;
+        // An unknown number is stored as 0xFFFFFFFF
+        let free = le_u32(info, 488);
+        Ok((signed && free <= clusters.count).then_some(free))
+    }
+
+    /// Counts the free clusters in the allocation table, which takes a read of the whole table.
+    fn count_free_clusters(&mut self, clusters: &Clusters) -> Result<u64, StorageError> {
+        let entry_len = if clusters.fat32 { 4 } else { 2 };
+        // The first two entries don't stand for clusters
+        let entries = clusters.count as usize + 2;
+        let fat_blocks = (entries * entry_len).div_ceil(Block::LEN);
+
+        let mut blocks: [Block; FAT_READ_BLOCKS] = core::array::from_fn(|_| Block::new());
+        let device = self.ctl.device();
+        let mut free = 0;
+        let mut entry = 0;
+        for first in (0..fat_blocks).step_by(FAT_READ_BLOCKS) {
+            let count = FAT_READ_BLOCKS.min(fat_blocks - first);
+            let start = BlockIdx(clusters.fat_start + first as u32);
+            device.read(&mut blocks[..count], start, "fat")?;
+            for block in &blocks[..count] {
+                for bytes in block.contents.chunks_exact(entry_len) {
+                    let value = if clusters.fat32 {
+                        le_u32(bytes, 0) & 0x0FFF_FFFF
+                    } else {
+                        le_u16(bytes, 0)
+                    };
+                    if (2..entries).contains(&entry) && value == 0 {
+                        free += 1;
+                    }
+                    entry += 1;
+                }
+            }
+        }
+        Ok(free)
+    }
+
+    /// Reads the cluster layout of the volume from the partition table and its boot sector,
+    /// embedded-sdmmc keeps the one it mounted to itself.
+    fn clusters(&mut self, partition: u8) -> Result<Clusters, StorageError> {
+        let mut blocks = [Block::new()];
+        let device = self.ctl.device();
+        device.read(&mut blocks, BlockIdx(0), "mbr")?;
+        let entry = 446 + 16 * partition as usize;
+        let lba_start = le_u32(&blocks[0].contents, entry + 8);
+
+        device.read(&mut blocks, BlockIdx(lba_start), "bpb")?;
+        let bpb = &blocks[0].contents;
+        let blocks_per_cluster = (bpb[13] as u32).max(1);
+        let reserved = le_u16(bpb, 14);
+        let fats = bpb[16] as u32;
+        let root_blocks = (le_u16(bpb, 17) * 32).div_ceil(Block::LEN_U32);
+        let total = match le_u16(bpb, 19) {
+            0 => le_u32(bpb, 32),
+            total => total,
+        };
+        let fat_blocks = match le_u16(bpb, 22) {
+            0 => le_u32(bpb, 36),
+            fat_blocks => fat_blocks,
+        };
+        let data_blocks = total.saturating_sub(reserved + fats * fat_blocks + root_blocks);
+        let count = data_blocks / blocks_per_cluster;
+        // The FAT type is told by the number of clusters only
+        let fat32 = count >= 65525;
+        let info_block = match le_u16(bpb, 48) {
+            0 | 0xFFFF => None,
+            sector => fat32.then_some(lba_start + sector),
+        };
+        Ok(Clusters {
+            fat_start: lba_start + reserved,
+            count,
+            size: blocks_per_cluster * Block::LEN_U32,
+            fat32,
+            info_block,
+        })
+    }
+
+    /// Returns the name of the first file whose name matches.
+    pub fn find_file(
+        &mut self,
+        matches: &dyn Fn(&str) -> bool,
+    ) -> Result<Option<FileName>, StorageError> {
+        let mut found = None;
+        self.ctl.iterate_dir(&self.vol, &self.dir, |entry| {
+            let is_file = !entry.attributes.is_directory() && !entry.attributes.is_volume();
+            if found.is_none() && is_file {
+                let name = file_name(&entry.name);
+                if matches(&name) {
+                    found = Some(name);
+                }
+            }
+        })?;
+        Ok(found)
+    }
+
+    /// Calls `visit` with the name and size of every file in the directory.
+    pub fn list_files(&mut self, visit: &mut dyn FnMut(&str, usize)) -> Result<(), StorageError> {
+        self.ctl.iterate_dir(&self.vol, &self.dir, |entry| {
+            let is_file = !entry.attributes.is_directory() && !entry.attributes.is_volume();
+            if is_file {
+                visit(&file_name(&entry.name), entry.size as usize);
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Calls `visit` with the name of every directory in the directory.
+    pub fn list_dirs(&mut self, visit: &mut dyn FnMut(&str)) -> Result<(), StorageError> {
+        self.ctl.iterate_dir(&self.vol, &self.dir, |entry| {
+            if entry.attributes.is_directory() && !entry.attributes.is_volume() {
+                visit(&file_name(&entry.name));
+            }
+        })?;
+        Ok(())
+    }
+
+    pub fn copy_file(&mut self, src: &str, dst: &str) -> Result<bool, StorageError> {
+        let mut src_file = self.ctl.open_file_in_dir(
+            &mut self.vol,
+            &self.dir,
+            src,
+            embedded_sdmmc::Mode::ReadOnly,
+        )?;
+        let mut dst_file = self.ctl.open_file_in_dir(
+            &mut self.vol,
+            &self.dir,
+            dst,
+            embedded_sdmmc::Mode::ReadWriteCreateOrTruncate,
+        )?;
+        let mut buf = [0; Block::LEN];
+        loop {
+            match self.ctl.read(&self.vol, &mut src_file, &mut buf) {
+                Ok(size) => {
+                    if size == 0 {
+                        self.ctl.close_file(&self.vol, dst_file)?;
+                        self.ctl.close_file(&self.vol, src_file)?;
+                        return Ok(true);
+                    } else {
+                        self.ctl.write(&mut self.vol, &mut dst_file, &buf[..size])?;
+                    }
+                }
+                Err(embedded_sdmmc::Error::EndOfFile) => {
+                    self.ctl.close_file(&self.vol, dst_file)?;
+                    self.ctl.close_file(&self.vol, src_file)?;
+                    return Ok(true);
+                }
+                Err(err) => {
+                    self.ctl.close_file(&self.vol, dst_file)?;
+                    self.ctl.close_file(&self.vol, src_file)?;
+                    return Err(err.into());
+                }
+            };
+        }
+    }
+
+    pub fn read(&mut self, file: &mut SdMmcFile, buf: &mut [u8]) -> Result<usize, StorageError> {
+        let size = self.ctl.read(&self.vol, file, buf)?;
+        Ok(size)
+    }
+
+    pub fn write(&mut self, file: &mut SdMmcFile, buf: &[u8]) -> Result<usize, StorageError> {
+        let size = self.ctl.write(&mut self.vol, file, buf)?;
+        Ok(size)
+    }
+}
+
+/// Name of the directory entry as `BASE.EXT`.
+fn file_name(name: &ShortFileName) -> FileName {
+    let mut file_name = FileName::new();
+    file_name
+        .push_str(core::str::from_utf8(name.base_name()).unwrap_or_default())
+        .ok();
+    if !name.extension().is_empty() {
+        file_name.push('.').ok();
+        file_name
+            .push_str(core::str::from_utf8(name.extension()).unwrap_or_default())
+            .ok();
+    }
+    file_name
+}
+
+fn le_u16(bytes: &[u8], offset: usize) -> u32 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]]) as u32
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+/// Encodes the name the way it is stored in a directory entry, 8 characters of the base name
+/// and 3 characters of the extension padded with spaces.
+fn short_name(name: &str) -> Result<[u8; 11], StorageError> {
+    let invalid = |err| StorageError::SdMmcController(ControllerError::FilenameError(err));
+    let (base, extension) = name.split_once('.').unwrap_or((name, ""));
+    if base.is_empty() {
+        return Err(invalid(FilenameError::FilenameEmpty));
+    }
+    if base.len() > 8 || extension.len() > 3 {
+        return Err(invalid(FilenameError::NameTooLong));
+    }
+    if extension.contains('.') {
+        return Err(invalid(FilenameError::MisplacedPeriod));
+    }
+
+    let mut short = [b' '; 11];
+    let bytes = base.bytes().zip(0..).chain(extension.bytes().zip(8..));
+    for (byte, pos) in bytes {
+        if !byte.is_ascii_alphanumeric() && !b"!#$%&'()-@^_`{}~".contains(&byte) {
+            return Err(invalid(FilenameError::InvalidCharacter));
+        }
+        short[pos] = byte.to_ascii_uppercase();
+    }
+    Ok(short)
+}
@@ -0,0 +1,43 @@
+use embedded_hal::{blocking::spi::Transfer, digital::v2::OutputPin};
+use embedded_sdmmc::{sdmmc::Error as SpiError, BlockDevice, BlockSpi, SdMmcSpi};
+
+/// Source of block devices the card is mounted from.
+///
+/// The block device is acquired for the duration of a card session and released afterwards, so
+/// the card is re-initialised once per session.
+pub trait Disk {
+    type Device<'a>: BlockDevice<Error = SpiError>
+    where
+        Self: 'a;
+
+    fn acquire(&mut self) -> Result<Self::Device<'_>, SpiError>;
+
+    /// Sets the SPI clock the following sessions drive the card with.
+    fn set_spi_clock(&mut self, khz: u32);
+}
+
+/// SPI bus whose clock can be changed once the card is set up.
+pub trait SpiClock {
+    /// Switches to the fastest clock which doesn't exceed `khz`.
+    fn set_clock(&mut self, khz: u32);
+}
+
+impl<SPI, CS> Disk for SdMmcSpi<SPI, CS>
+where
+    SPI: Transfer<u8> + SpiClock,
+    SPI::Error: core::fmt::Debug,
+    CS: OutputPin,
+{
+    type Device<'a>
+        = BlockSpi<'a, SPI, CS>
+    where
+        Self: 'a;
+
+    fn acquire(&mut self) -> Result<Self::Device<'_>, SpiError> {
+        SdMmcSpi::acquire(self)
+    }
+
+    fn set_spi_clock(&mut self, khz: u32) {
+        self.spi().set_clock(khz);
+    }
+}
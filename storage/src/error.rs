@@ -1,27 +1,22 @@
 use embedded_sdmmc::sdmmc::Error as SpiError;
 use embedded_sdmmc::Error as SdMmcControllerError;
 use embedded_sdmmc::FilenameError;
+use sm2m_protocol::error::Fault;
 
 pub type ControllerError = SdMmcControllerError<SpiError>;
 
 #[derive(Clone)]
-pub enum AppError {
-    SdmmcDetached,
-    UnhandledReadyCommand,
-    UnhandledAddressCommand,
+pub enum StorageError {
     SdMmcSpi(SpiError),
     SdMmcController(ControllerError),
     SdMmcFile(embedded_sdmmc::filesystem::FileError),
 }
 
-impl AppError {
+impl StorageError {
     pub fn opcode(&self) -> u16 {
-        use AppError::*;
+        use StorageError::*;
 
         match self {
-            SdmmcDetached => 1,
-            UnhandledReadyCommand => 2,
-            UnhandledAddressCommand => 3,
             SdMmcController(ControllerError::DeviceError(SpiError::Transport))
             | SdMmcSpi(SpiError::Transport) => 4,
             SdMmcController(ControllerError::DeviceError(SpiError::CantEnableCRC))
@@ -81,26 +76,64 @@ impl AppError {
     }
 }
 
-impl From<SpiError> for AppError {
+impl From<SpiError> for StorageError {
     fn from(value: SpiError) -> Self {
         Self::SdMmcSpi(value)
     }
 }
 
-impl From<ControllerError> for AppError {
+impl From<ControllerError> for StorageError {
     fn from(value: ControllerError) -> Self {
         Self::SdMmcController(value)
     }
 }
 
-impl From<embedded_sdmmc::filesystem::FileError> for AppError {
+impl From<embedded_sdmmc::filesystem::FileError> for StorageError {
     fn from(value: embedded_sdmmc::filesystem::FileError) -> Self {
         Self::SdMmcFile(value)
     }
 }
 
-impl From<AppError> for u16 {
-    fn from(value: AppError) -> Self {
+impl From<StorageError> for u16 {
+    fn from(value: StorageError) -> Self {
         value.opcode()
     }
 }
+
+impl From<StorageError> for Fault {
+    fn from(value: StorageError) -> Self {
+        use StorageError::*;
+
+        let params = match &value {
+            SdMmcController(ControllerError::DeviceError(SpiError::TimeoutCommand(command)))
+            | SdMmcSpi(SpiError::TimeoutCommand(command))
+            | SdMmcController(ControllerError::DeviceError(SpiError::TimeoutACommand(command)))
+            | SdMmcSpi(SpiError::TimeoutACommand(command)) => [*command as u16, 0],
+            SdMmcController(ControllerError::DeviceError(SpiError::CrcError(expected, actual)))
+            | SdMmcSpi(SpiError::CrcError(expected, actual)) => [*expected, *actual],
+            SdMmcController(ControllerError::BadBlockSize(size)) => [*size, 0],
+            _ => [0; 2],
+        };
+        let retryable = matches!(
+            value,
+            SdMmcController(ControllerError::DeviceError(
+                SpiError::TimeoutReadBuffer
+                    | SpiError::TimeoutWaitNotBusy
+                    | SpiError::TimeoutCommand(_)
+                    | SpiError::TimeoutACommand(_)
+                    | SpiError::CrcError(_, _)
+            )) | SdMmcSpi(
+                SpiError::TimeoutReadBuffer
+                    | SpiError::TimeoutWaitNotBusy
+                    | SpiError::TimeoutCommand(_)
+                    | SpiError::TimeoutACommand(_)
+                    | SpiError::CrcError(_, _)
+            )
+        );
+        Self {
+            opcode: value.opcode(),
+            params,
+            retryable,
+        }
+    }
+}
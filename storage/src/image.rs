@@ -0,0 +1,96 @@
+use std::{cell::RefCell, fs, io, path::Path};
+
+use embedded_sdmmc::{sdmmc::Error as SpiError, Block, BlockCount, BlockDevice, BlockIdx};
+
+use crate::disk::Disk;
+
+/// SD card image kept in RAM.
+///
+/// The image must contain an MBR partition table followed by FAT16 or FAT32 volume, exactly as
+/// it would be read from a physical card with `dd`.
+pub struct ImageDisk {
+    data: Vec<u8>,
+}
+
+impl ImageDisk {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data }
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::read(path).map(Self::new)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, &self.data)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn free(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+impl Disk for ImageDisk {
+    type Device<'a> = ImageDevice<'a>;
+
+    fn acquire(&mut self) -> Result<Self::Device<'_>, SpiError> {
+        Ok(ImageDevice {
+            data: RefCell::new(&mut self.data),
+        })
+    }
+
+    /// The image is read at the speed of the memory.
+    fn set_spi_clock(&mut self, _khz: u32) {}
+}
+
+/// Block device view of the image, reports failures the same way the SPI card does.
+pub struct ImageDevice<'a> {
+    data: RefCell<&'a mut Vec<u8>>,
+}
+
+impl<'a> ImageDevice<'a> {
+    fn range(&self, block_idx: BlockIdx, count: usize) -> Option<core::ops::Range<usize>> {
+        let start = block_idx.0 as usize * Block::LEN;
+        let end = start + count * Block::LEN;
+        (end <= self.data.borrow().len()).then_some(start..end)
+    }
+}
+
+impl<'a> BlockDevice for ImageDevice<'a> {
+    type Error = SpiError;
+
+    fn read(
+        &self,
+        blocks: &mut [Block],
+        start_block_idx: BlockIdx,
+        _reason: &str,
+    ) -> Result<(), Self::Error> {
+        let range = self
+            .range(start_block_idx, blocks.len())
+            .ok_or(SpiError::ReadError)?;
+        let data = self.data.borrow();
+        for (block, chunk) in blocks.iter_mut().zip(data[range].chunks(Block::LEN)) {
+            block.contents.copy_from_slice(chunk);
+        }
+        Ok(())
+    }
+
+    fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+        let range = self
+            .range(start_block_idx, blocks.len())
+            .ok_or(SpiError::WriteError)?;
+        let mut data = self.data.borrow_mut();
+        for (block, chunk) in blocks.iter().zip(data[range].chunks_mut(Block::LEN)) {
+            chunk.copy_from_slice(&block.contents);
+        }
+        Ok(())
+    }
+
+    fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+        Ok(BlockCount((self.data.borrow().len() / Block::LEN) as u32))
+    }
+}
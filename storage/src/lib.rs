@@ -0,0 +1,22 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub mod card;
+pub mod controller;
+pub mod disk;
+pub mod error;
+#[cfg(feature = "std")]
+pub mod image;
+pub mod time;
+
+pub use card::Card;
+pub use controller::Controller;
+pub use disk::{Disk, SpiClock};
+pub use error::StorageError;
+#[cfg(feature = "std")]
+pub use image::ImageDisk;
+pub use time::{Clock, ClockTimeSource};
+
+pub type SdMmcController<D, T> = embedded_sdmmc::Controller<D, T>;
+pub type SdMmcVolume = embedded_sdmmc::Volume;
+pub type SdMmcDirectory = embedded_sdmmc::Directory;
+pub type SdMmcFile = embedded_sdmmc::File;
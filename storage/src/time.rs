@@ -0,0 +1,100 @@
+use embedded_sdmmc::{TimeSource, Timestamp};
+use sm2m_protocol::time::{date, days, SECONDS_PER_DAY};
+
+/// File the clock is set from when the card is mounted at boot.
+pub const TIME_FILE_NAME: &str = "TIME.TXT";
+
+/// Time files are stamped with while the clock was never set, 1 Jan 2023 00:00.
+pub const DEFAULT_TIME: u32 = 1_672_531_200;
+
+/// Seconds counter which keeps running while the adapter is powered off.
+///
+/// Clones share the same counter, so the clock set through one of them is seen by the others.
+pub trait Clock: Clone {
+    /// Seconds since 1970-01-01 00:00:00 local time once the clock was set, otherwise seconds
+    /// since the counter started.
+    fn counter(&self) -> u32;
+
+    /// Whether the counter was set to the calendar time.
+    fn is_set(&self) -> bool;
+
+    /// Sets the counter to `seconds` since 1970-01-01 00:00:00 local time.
+    fn set(&mut self, seconds: u32);
+}
+
+/// Stamps files with the clock time.
+///
+/// While the clock was never set the time counts up from `DEFAULT_TIME`, so files written later
+/// still get later timestamps.
+pub struct ClockTimeSource<C>(pub C);
+
+impl<C: Clock> ClockTimeSource<C> {
+    /// Seconds since 1970-01-01 00:00:00.
+    pub fn now(&self) -> u32 {
+        if self.0.is_set() {
+            self.0.counter()
+        } else {
+            DEFAULT_TIME.saturating_add(self.0.counter())
+        }
+    }
+}
+
+impl<C: Clock> TimeSource for ClockTimeSource<C> {
+    fn get_timestamp(&self) -> Timestamp {
+        timestamp(self.now())
+    }
+}
+
+/// Splits seconds since 1970-01-01 00:00:00 into calendar date and time.
+pub fn timestamp(seconds: u32) -> Timestamp {
+    let time = seconds % SECONDS_PER_DAY;
+    let (year, month, day) = date(seconds / SECONDS_PER_DAY);
+    Timestamp {
+        year_since_1970: (year - 1970) as u8,
+        zero_indexed_month: (month - 1) as u8,
+        zero_indexed_day: (day - 1) as u8,
+        hours: (time / 3600) as u8,
+        minutes: (time / 60 % 60) as u8,
+        seconds: (time % 60) as u8,
+    }
+}
+
+/// Parses `YYYY-MM-DD HH:MM:SS` local time into seconds since 1970-01-01 00:00:00.
+///
+/// Years outside of 1980..=2105 are rejected since FAT can't store them.
+pub fn parse_time(text: &[u8]) -> Option<u32> {
+    let text = text.trim_ascii();
+    if text.len() != 19 {
+        return None;
+    }
+
+    let field = |range: core::ops::Range<usize>| -> Option<u32> {
+        let digits = &text[range];
+        digits.iter().try_fold(0, |value, digit| {
+            digit
+                .is_ascii_digit()
+                .then(|| value * 10 + (digit - b'0') as u32)
+        })
+    };
+    let separators = [(4, b'-'), (7, b'-'), (13, b':'), (16, b':')];
+    if separators.iter().any(|&(pos, sep)| text[pos] != sep) || !b" T".contains(&text[10]) {
+        return None;
+    }
+
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let (hours, minutes, seconds) = (field(11..13)?, field(14..16)?, field(17..19)?);
+    if !(1980..=2105).contains(&year) || !(1..=12).contains(&month) || day < 1 {
+        return None;
+    }
+    if hours > 23 || minutes > 59 || seconds > 59 {
+        return None;
+    }
+
+    let days = days(year, month, day);
+    // Days past the end of the month roll over to the next one
+    if date(days) != (year, month, day) {
+        return None;
+    }
+
+    Some(days * SECONDS_PER_DAY + hours * 3600 + minutes * 60 + seconds)
+}
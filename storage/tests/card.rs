@@ -0,0 +1,568 @@
+mod common;
+
+use common::{
+    create_dir, disk, image, modified, read_file, set_info_free_clusters, space, words_to_bytes,
+    write_file, Harness, TestClock,
+};
+use fatfs::FatType;
+use sm2m_protocol::{
+    adapter::IO_BUFFER_SIZE,
+    bus::{input, output},
+    storage::IMAGE_FILE_NAME,
+    Config, Drives, Layout, Storage,
+};
+use sm2m_storage::{Card, Clock};
+
+const FAT_TYPES: [FatType; 2] = [FatType::Fat16, FatType::Fat32];
+const FILE_NOT_FOUND: u16 = 27;
+const CARD_FULL: u16 = 51;
+const INVALID_FILE_OFFSET: u16 = 44;
+
+#[test]
+fn check_status_mounts_volume() {
+    for fat_type in FAT_TYPES {
+        let mut adapter = Harness::new(image(fat_type));
+
+        assert_eq!(
+            adapter.command(input::Frame::CheckStatus),
+            output::Frame::Ack
+        );
+    }
+}
+
+#[test]
+fn check_status_fails_without_partition_table() {
+    let mut adapter = Harness::new(vec![0; 1024 * 1024]);
+
+    assert_eq!(
+        adapter.command(input::Frame::CheckStatus),
+        output::Frame::Error(1)
+    );
+}
+
+#[test]
+fn written_file_is_readable_by_other_fat_implementations() {
+    for fat_type in FAT_TYPES {
+        let words = [0x1234, 0xABCD, 0x0000, 0xFFFF];
+        let mut adapter = Harness::new(image(fat_type));
+
+        adapter.write_file(12, &words);
+
+        let mut image = adapter.image();
+        assert_eq!(read_file(&mut image, "12"), Some(words_to_bytes(&words)));
+    }
+}
+
+#[test]
+fn write_replaces_existing_file() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        write_file(&mut image, "12", &[0xAA; 4096]);
+        let mut adapter = Harness::new(image);
+
+        adapter.write_file(12, &[0x0102]);
+
+        let mut image = adapter.image();
+        assert_eq!(read_file(&mut image, "12"), Some(vec![0x02, 0x01]));
+    }
+}
+
+#[test]
+fn drive_directory_holds_its_files() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        create_dir(&mut image, "DRIVE1");
+        let mut adapter = Harness::new(image);
+        adapter.configure(Config {
+            drives: Drives::Directories,
+            ..Config::default()
+        });
+
+        for frame in [input::Frame::Drive, input::Frame::Data(1)] {
+            assert_eq!(adapter.command(frame), output::Frame::Ack);
+        }
+        adapter.write_file(3, &[0x0102]);
+
+        let mut image = adapter.image();
+        assert_eq!(read_file(&mut image, "DRIVE1/3"), Some(vec![0x02, 0x01]));
+        assert_eq!(read_file(&mut image, "3"), None);
+    }
+}
+
+#[test]
+fn read_returns_file_created_on_pc() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        write_file(&mut image, "3", &[0x01, 0x02, 0x03, 0x04]);
+        let mut adapter = Harness::new(image);
+
+        assert_eq!(adapter.read_file(3, 2), [0x0201, 0x0403]);
+    }
+}
+
+#[test]
+fn list_answers_addresses_of_files_in_directory() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        write_file(&mut image, "3", &[0; 4]);
+        write_file(&mut image, "3.BK1", &[0; 2]);
+        write_file(&mut image, "ADAPTER.CFG", b"log = off");
+        let mut adapter = Harness::new(image);
+        adapter.write_file(12, &[1, 2, 3]);
+
+        assert_eq!(adapter.command(input::Frame::ListSizes), output::Frame::Ack);
+        let mut words = Vec::new();
+        while let output::Frame::Data(word) = adapter.command(input::Frame::Data(0)) {
+            words.push(word);
+        }
+        assert_eq!(words, [3, 0, 2, 12, 0, 3]);
+    }
+}
+
+#[test]
+fn read_of_missing_file_reports_file_not_found() {
+    for fat_type in FAT_TYPES {
+        let mut adapter = Harness::new(image(fat_type));
+        adapter.command(input::Frame::Address(5));
+
+        assert_eq!(
+            adapter.command(input::Frame::Read),
+            output::Frame::Error(FILE_NOT_FOUND)
+        );
+    }
+}
+
+#[test]
+fn transfers_span_multiple_buffers() {
+    for fat_type in FAT_TYPES {
+        let words: Vec<u16> = (0..(IO_BUFFER_SIZE as u16 + 100)).collect();
+        let mut adapter = Harness::new(image(fat_type));
+
+        adapter.write_file(40, &words);
+        assert_eq!(adapter.read_file(40, words.len()), words);
+
+        let mut image = adapter.image();
+        assert_eq!(read_file(&mut image, "40"), Some(words_to_bytes(&words)));
+    }
+}
+
+#[test]
+fn image_sector_is_overwritten_in_place() {
+    const SECTOR_SIZE: usize = 1000;
+
+    for fat_type in FAT_TYPES {
+        let data: Vec<u8> = (0..4 * SECTOR_SIZE).map(|i| i as u8).collect();
+        let mut image = image(fat_type);
+        write_file(&mut image, IMAGE_FILE_NAME, &data);
+        let mut adapter = Harness::new(image);
+        adapter.configure(Config {
+            layout: Layout::Image {
+                sector_size: SECTOR_SIZE,
+            },
+            ..Config::default()
+        });
+        let words = [0xAAAA; 10];
+
+        adapter.write_file(1, &words);
+
+        assert_eq!(adapter.read_file(1, words.len()), words);
+        let mut expected = data;
+        expected[SECTOR_SIZE..SECTOR_SIZE + 20].copy_from_slice(&[0xAA; 20]);
+        let mut image = adapter.image();
+        assert_eq!(read_file(&mut image, IMAGE_FILE_NAME), Some(expected));
+    }
+}
+
+#[test]
+fn write_after_seek_keeps_file_size() {
+    for fat_type in FAT_TYPES {
+        let data: Vec<u8> = (0..4000).map(|i| i as u8).collect();
+        let mut image = image(fat_type);
+        write_file(&mut image, "3", &data);
+        let mut adapter = Harness::new(image);
+
+        adapter.command(input::Frame::Address(3));
+        for frame in [
+            input::Frame::Seek,
+            input::Frame::Data(0),
+            input::Frame::Data(300),
+            input::Frame::Write,
+        ] {
+            assert_eq!(adapter.command(frame), output::Frame::Ack);
+        }
+        for _ in 0..10 {
+            adapter.command(input::Frame::Data(0xAAAA));
+        }
+        assert_eq!(adapter.send(input::Action::Stop), output::Frame::Ack);
+
+        adapter.command(input::Frame::Address(3));
+        assert_eq!(adapter.command(input::Frame::Size), output::Frame::Data(0));
+        assert_eq!(
+            adapter.command(input::Frame::Data(0)),
+            output::Frame::Data(2000)
+        );
+        adapter.send(input::Action::Stop);
+
+        let mut expected = data;
+        expected[600..620].copy_from_slice(&[0xAA; 20]);
+        let mut image = adapter.image();
+        assert_eq!(read_file(&mut image, "3"), Some(expected));
+    }
+}
+
+#[test]
+fn read_after_seek_past_end_of_file_ends_right_away() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        write_file(&mut image, "3", &[0; 4000]);
+        let mut adapter = Harness::new(image);
+
+        // Within the last buffer of the file and past it
+        for offset in [2001, 6000] {
+            adapter.command(input::Frame::Address(3));
+            for frame in [
+                input::Frame::Seek,
+                input::Frame::Data(0),
+                input::Frame::Data(offset),
+            ] {
+                assert_eq!(adapter.command(frame), output::Frame::Ack);
+            }
+            assert_eq!(adapter.command(input::Frame::Read), output::Frame::Ack);
+            assert_eq!(adapter.command(input::Frame::Data(0)), output::Frame::End);
+            assert_eq!(adapter.send(input::Action::Stop), output::Frame::Ack);
+        }
+    }
+}
+
+#[test]
+fn offsets_past_fat_file_limit_are_rejected() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        write_file(&mut image, IMAGE_FILE_NAME, &[0; 16]);
+        let mut card = Card::new(disk(image), TestClock::default());
+        let offset = u32::MAX as usize + 1;
+
+        let result = card.read_file(IMAGE_FILE_NAME, offset, &mut [0; 2]);
+        assert_eq!(result.map_err(|err| err.opcode()), Err(INVALID_FILE_OFFSET));
+        let result = card.write_file(IMAGE_FILE_NAME, offset, &[0; 2]);
+        assert_eq!(result.map_err(|err| err.opcode()), Err(INVALID_FILE_OFFSET));
+    }
+}
+
+#[test]
+fn files_are_stamped_with_time_set_over_bus() {
+    for fat_type in FAT_TYPES {
+        let mut adapter = Harness::new(image(fat_type));
+
+        adapter.set_time(1_718_630_130); // 2024-06-17 13:15:30
+        adapter.write_file(7, &[0x0001]);
+
+        let mut image = adapter.image();
+        let modified = modified(&mut image, "7").unwrap();
+        assert_eq!(
+            (modified.date.year, modified.date.month, modified.date.day),
+            (2024, 6, 17)
+        );
+        assert_eq!(
+            (modified.time.hour, modified.time.min, modified.time.sec),
+            (13, 15, 30)
+        );
+    }
+}
+
+#[test]
+fn time_counts_up_from_default_until_clock_is_set() {
+    for fat_type in FAT_TYPES {
+        let clock = TestClock::stopped_at(24 * 60 * 60 + 3662);
+        let mut adapter = Harness::with_clock(image(fat_type), clock);
+
+        adapter.write_file(7, &[0x0001]);
+
+        let mut image = adapter.image();
+        let modified = modified(&mut image, "7").unwrap();
+        assert_eq!(
+            (modified.date.year, modified.date.month, modified.date.day),
+            (2023, 1, 2)
+        );
+        assert_eq!(
+            (modified.time.hour, modified.time.min, modified.time.sec),
+            (1, 1, 2)
+        );
+    }
+}
+
+#[test]
+fn clock_is_set_from_time_file() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        write_file(&mut image, "TIME.TXT", b"2024-06-17 13:15:30\r\n");
+        let clock = TestClock::default();
+        let mut card = Card::new(disk(image), clock.clone());
+
+        assert!(matches!(card.sync_clock(), Ok(true)));
+        assert!(clock.is_set());
+        assert_eq!(clock.counter(), 1_718_630_130);
+
+        let mut image = card.free().as_bytes().to_vec();
+        assert_eq!(read_file(&mut image, "TIME.TXT"), None);
+    }
+}
+
+#[test]
+fn invalid_time_file_is_kept() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        write_file(&mut image, "TIME.TXT", b"17.06.2024 13:15");
+        let clock = TestClock::default();
+        let mut card = Card::new(disk(image), clock.clone());
+
+        assert!(matches!(card.sync_clock(), Ok(false)));
+        assert!(!clock.is_set());
+
+        let mut image = card.free().as_bytes().to_vec();
+        assert!(read_file(&mut image, "TIME.TXT").is_some());
+    }
+}
+
+#[test]
+fn backups_are_rotated_and_restored() {
+    for fat_type in FAT_TYPES {
+        let mut adapter = Harness::new(image(fat_type));
+        adapter.configure(Config {
+            backups: 2,
+            ..Config::default()
+        });
+        for word in 1..=3 {
+            adapter.write_file(9, &[word]);
+        }
+
+        assert_eq!(
+            adapter.command(input::Frame::Address(9)),
+            output::Frame::Ack
+        );
+        assert_eq!(adapter.command(input::Frame::Restore), output::Frame::Ack);
+
+        let mut image = adapter.image();
+        assert_eq!(read_file(&mut image, "9"), Some(words_to_bytes(&[2])));
+        assert_eq!(read_file(&mut image, "9.BK1"), Some(words_to_bytes(&[1])));
+        assert_eq!(read_file(&mut image, "9.BK2"), None);
+    }
+}
+
+#[test]
+fn moved_and_deleted_files_are_seen_by_other_fat_implementations() {
+    for fat_type in FAT_TYPES {
+        let mut adapter = Harness::new(image(fat_type));
+        adapter.write_file(1, &[1]);
+        adapter.write_file(2, &[2]);
+
+        adapter.command(input::Frame::Address(1));
+        adapter.command(input::Frame::Move);
+        assert_eq!(adapter.command(input::Frame::Data(300)), output::Frame::Ack);
+        adapter.command(input::Frame::Address(2));
+        assert_eq!(adapter.command(input::Frame::Delete), output::Frame::Ack);
+
+        let mut image = adapter.image();
+        assert_eq!(read_file(&mut image, "300"), Some(words_to_bytes(&[1])));
+        assert_eq!(read_file(&mut image, "1"), None);
+        assert_eq!(read_file(&mut image, "2"), None);
+    }
+}
+
+#[test]
+fn deleted_and_replaced_files_free_their_clusters() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        let (_, free) = space(&mut image);
+        let words = vec![0x5A5A; 20 * 1024];
+
+        // Every write replaces the file with its temporary file and drops the oldest backup
+        let mut adapter = Harness::new(image);
+        adapter.configure(Config {
+            backups: 1,
+            ..Config::default()
+        });
+        for _ in 0..5 {
+            adapter.write_file(1, &words);
+        }
+        let mut image = adapter.image();
+        assert_eq!(space(&mut image).1, free - 2 * 40 * 1024);
+
+        // Without backups nothing is kept of the deleted file
+        let mut adapter = Harness::new(image);
+        adapter.command(input::Frame::Address(1));
+        assert_eq!(adapter.command(input::Frame::Delete), output::Frame::Ack);
+        let mut image = adapter.image();
+        assert_eq!(read_file(&mut image, "1"), None);
+        assert_eq!(read_file(&mut image, "1.BK1"), Some(words_to_bytes(&words)));
+        assert_eq!(space(&mut image).1, free - 40 * 1024);
+
+        if fat_type == FatType::Fat32 {
+            // The free space of FAT32 is taken from FSInfo, the allocation table has to agree
+            set_info_free_clusters(&mut image, u32::MAX);
+            assert_eq!(space(&mut image).1, free - 40 * 1024);
+        }
+    }
+}
+
+#[test]
+fn capacity_is_counted_from_allocation_table() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        write_file(&mut image, "3", &[0; 100_000]);
+        let (total, free) = space(&mut image);
+        let mut adapter = Harness::new(image);
+
+        let capacity = adapter.capacity();
+        assert_eq!(capacity, ((total / 1024) as u32, (free / 1024) as u32));
+    }
+}
+
+#[test]
+fn free_space_of_fat32_is_taken_from_fsinfo() {
+    let mut image = image(FatType::Fat32);
+    let (_, free) = space(&mut image);
+
+    // Clusters of the test image take 512 bytes
+    set_info_free_clusters(&mut image, 1000);
+    let mut adapter = Harness::new(image.clone());
+    assert_eq!(adapter.capacity().1, 500);
+
+    // An unknown number is counted in the allocation table
+    set_info_free_clusters(&mut image, u32::MAX);
+    let mut adapter = Harness::new(image);
+    assert_eq!(adapter.capacity().1, (free / 1024) as u32);
+}
+
+#[test]
+fn capacity_recovers_after_delete_and_rewrite() {
+    for fat_type in FAT_TYPES {
+        let mut adapter = Harness::new(image(fat_type));
+        let (_, free) = adapter.capacity();
+        // Enough for the file, its backup and its temporary file, not for leaked clusters
+        let config = Config {
+            backups: 1,
+            reserve: free - 200,
+            ..Config::default()
+        };
+        adapter.configure(config);
+
+        let words = vec![0x5A5A; 20 * 1024];
+        for _ in 0..10 {
+            adapter.write_file(1, &words);
+        }
+        assert_eq!(adapter.capacity().1, free - 80);
+
+        adapter.configure(Config {
+            backups: 0,
+            ..config
+        });
+        adapter.command(input::Frame::Address(1));
+        assert_eq!(adapter.command(input::Frame::Delete), output::Frame::Ack);
+        assert_eq!(adapter.capacity().1, free - 40);
+    }
+}
+
+#[test]
+fn write_is_refused_when_card_is_full() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        let (_, free) = space(&mut image);
+        let mut adapter = Harness::new(image);
+        adapter.configure(Config {
+            reserve: (free / 1024) as u32 + 1,
+            ..Config::default()
+        });
+
+        adapter.command(input::Frame::Address(1));
+        assert_eq!(
+            adapter.command(input::Frame::Write),
+            output::Frame::Error(CARD_FULL)
+        );
+    }
+}
+
+#[test]
+fn temp_files_are_discarded() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        write_file(&mut image, "5", &[0x01, 0x02]);
+        write_file(&mut image, "5.TMP", &[0xAA; 4096]);
+        write_file(&mut image, "5.CRT", &[0; 4]);
+        write_file(&mut image, "6.TMP", &[0xBB; 16]);
+        write_file(&mut image, "5.CRC", &[0; 4]);
+        write_file(&mut image, "NOTES.TMP", b"keep");
+        write_file(&mut image, "05.TMP", b"keep");
+        create_dir(&mut image, "DRIVE1");
+        write_file(&mut image, "DRIVE1/7", &[0x03, 0x04]);
+        write_file(&mut image, "DRIVE1/7.TMP", &[0xCC; 16]);
+        let mut card = Card::new(disk(image), TestClock::default());
+
+        assert!(matches!(card.discard_temp_files(), Ok(3)));
+
+        let mut image = card.free().as_bytes().to_vec();
+        assert_eq!(read_file(&mut image, "5"), Some(vec![0x01, 0x02]));
+        assert_eq!(read_file(&mut image, "5.TMP"), None);
+        assert_eq!(read_file(&mut image, "6.TMP"), None);
+        assert_eq!(read_file(&mut image, "5.CRT"), None);
+        assert_eq!(read_file(&mut image, "5.CRC"), Some(vec![0; 4]));
+        assert_eq!(read_file(&mut image, "NOTES.TMP"), Some(b"keep".to_vec()));
+        assert_eq!(read_file(&mut image, "05.TMP"), Some(b"keep".to_vec()));
+        assert_eq!(read_file(&mut image, "DRIVE1/7"), Some(vec![0x03, 0x04]));
+        assert_eq!(read_file(&mut image, "DRIVE1/7.TMP"), None);
+    }
+}
+
+#[test]
+fn inserted_card_is_initialised() {
+    for fat_type in FAT_TYPES {
+        let mut image = image(fat_type);
+        write_file(&mut image, "TIME.TXT", b"2024-06-17 13:15:30");
+        write_file(&mut image, "6.TMP", &[0xBB; 16]);
+        write_file(&mut image, "ADAPTER.CFG", b"backups = 2\n");
+        let clock = TestClock::default();
+        let mut adapter = Harness::with_clock(image, clock.clone());
+
+        adapter.detect_card(true);
+        assert!(clock.is_set());
+        assert_eq!(adapter.config().backups, 2);
+        assert_eq!(
+            adapter.command(input::Frame::CheckStatus),
+            output::Frame::Ack
+        );
+
+        let mut image = adapter.image();
+        assert_eq!(read_file(&mut image, "TIME.TXT"), None);
+        assert_eq!(read_file(&mut image, "6.TMP"), None);
+    }
+}
+
+#[test]
+fn checksums_are_verified_on_read() {
+    for fat_type in FAT_TYPES {
+        let words: Vec<u16> = (0..(IO_BUFFER_SIZE as u16 / 2 + 100)).collect();
+        let mut adapter = Harness::new(image(fat_type));
+        adapter.configure(Config {
+            checksums: true,
+            ..Config::default()
+        });
+
+        adapter.write_file(40, &words);
+        assert_eq!(adapter.read_file(40, words.len()), words);
+
+        let mut image = adapter.image();
+        assert_eq!(
+            read_file(&mut image, "40.CRC").map(|crc| crc.len()),
+            Some(8)
+        );
+        let mut data = read_file(&mut image, "40").unwrap();
+        data[10] ^= 0x01;
+        write_file(&mut image, "40", &data);
+        let mut adapter = Harness::new(image);
+        adapter.command(input::Frame::Address(40));
+        assert_eq!(
+            adapter.command(input::Frame::Read),
+            output::Frame::Error(48)
+        );
+    }
+}
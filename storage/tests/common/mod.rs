@@ -0,0 +1,312 @@
+#![allow(dead_code)]
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    io::{Cursor, Read, Write},
+    rc::Rc,
+};
+
+use fatfs::{FatType, FileSystem, FormatVolumeOptions, FsOptions};
+use sm2m_protocol::{
+    adapter::IO_BUFFER_SIZE,
+    bus::{input, output, Input, Output, Parity},
+    worker::{Request, Spawn},
+    Config, Device, Indicators, Worker,
+};
+use sm2m_storage::{Card, Clock, ImageDisk};
+
+const SECTOR_SIZE: usize = 512;
+const PARTITION_START: usize = 2048; // sectors
+
+/// Creates SD card image with MBR and a single FAT partition of the given type.
+pub fn image(fat_type: FatType) -> Vec<u8> {
+    let (size, bytes_per_cluster, partition_type) = match fat_type {
+        FatType::Fat12 => panic!("FAT12 is not supported by the adapter"),
+        FatType::Fat16 => (16 * 1024 * 1024, 2048, 0x0E), // FAT16 LBA
+        FatType::Fat32 => (40 * 1024 * 1024, 512, 0x0C),  // FAT32 LBA
+    };
+
+    let mut data = vec![0; size];
+    let partition_sectors = (size / SECTOR_SIZE - PARTITION_START) as u32;
+    let options = FormatVolumeOptions::new()
+        .fat_type(fat_type)
+        .bytes_per_cluster(bytes_per_cluster)
+        .total_sectors(partition_sectors);
+    fatfs::format_volume(Cursor::new(partition(&mut data)), options).unwrap();
+
+    let entry = &mut data[446..462];
+    entry[4] = partition_type;
+    entry[8..12].copy_from_slice(&(PARTITION_START as u32).to_le_bytes());
+    entry[12..16].copy_from_slice(&partition_sectors.to_le_bytes());
+    data[510] = 0x55;
+    data[511] = 0xAA;
+    data
+}
+
+fn partition(data: &mut [u8]) -> &mut [u8] {
+    &mut data[PARTITION_START * SECTOR_SIZE..]
+}
+
+/// Reads the file from the image using an independent FAT implementation.
+pub fn read_file(image: &mut [u8], name: &str) -> Option<Vec<u8>> {
+    let fs = FileSystem::new(Cursor::new(partition(image)), FsOptions::new()).unwrap();
+    let mut file = fs.root_dir().open_file(name).ok()?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).unwrap();
+    Some(data)
+}
+
+/// Writes the file to the image using an independent FAT implementation.
+pub fn write_file(image: &mut [u8], name: &str, data: &[u8]) {
+    let fs = FileSystem::new(Cursor::new(partition(image)), FsOptions::new()).unwrap();
+    let mut file = fs.root_dir().create_file(name).unwrap();
+    file.truncate().unwrap();
+    file.write_all(data).unwrap();
+}
+
+/// Creates the directory in the root using an independent FAT implementation.
+pub fn create_dir(image: &mut [u8], name: &str) {
+    let fs = FileSystem::new(Cursor::new(partition(image)), FsOptions::new()).unwrap();
+    fs.root_dir().create_dir(name).unwrap();
+}
+
+/// Space for files and free space in bytes as counted by an independent FAT implementation.
+pub fn space(image: &mut [u8]) -> (u64, u64) {
+    let fs = FileSystem::new(Cursor::new(partition(image)), FsOptions::new()).unwrap();
+    let stats = fs.stats().unwrap();
+    let cluster_size = stats.cluster_size() as u64;
+    (
+        stats.total_clusters() as u64 * cluster_size,
+        stats.free_clusters() as u64 * cluster_size,
+    )
+}
+
+/// Stores the number of free clusters in the FSInfo sector of a FAT32 image.
+pub fn set_info_free_clusters(image: &mut [u8], count: u32) {
+    let partition = partition(image);
+    let sector = u16::from_le_bytes([partition[48], partition[49]]) as usize;
+    let offset = sector * SECTOR_SIZE + 488;
+    partition[offset..offset + 4].copy_from_slice(&count.to_le_bytes());
+}
+
+/// Last modification time of the file as stored by an independent FAT implementation.
+pub fn modified(image: &mut [u8], name: &str) -> Option<fatfs::DateTime> {
+    let fs = FileSystem::new(Cursor::new(partition(image)), FsOptions::new()).unwrap();
+    let entry = fs
+        .root_dir()
+        .iter()
+        .map(Result::unwrap)
+        .find(|entry| entry.file_name().eq_ignore_ascii_case(name))?;
+    Some(entry.modified())
+}
+
+/// Clock which stands still at the given counter value.
+#[derive(Clone, Default)]
+pub struct TestClock(Rc<Cell<(u32, bool)>>);
+
+impl TestClock {
+    pub fn stopped_at(counter: u32) -> Self {
+        let clock = Self::default();
+        clock.0.set((counter, false));
+        clock
+    }
+}
+
+impl Clock for TestClock {
+    fn counter(&self) -> u32 {
+        self.0.get().0
+    }
+
+    fn is_set(&self) -> bool {
+        self.0.get().1
+    }
+
+    fn set(&mut self, seconds: u32) {
+        self.0.set((seconds, true));
+    }
+}
+
+pub fn disk(image: Vec<u8>) -> &'static mut ImageDisk {
+    Box::leak(Box::new(ImageDisk::new(image)))
+}
+
+#[derive(Clone, Default)]
+pub struct InputBus(Rc<RefCell<VecDeque<input::Action>>>);
+
+impl Input for InputBus {
+    fn read(&mut self) -> input::Action {
+        self.0.borrow_mut().pop_front().expect("no pending action")
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct OutputBus(Rc<RefCell<Vec<output::Frame>>>);
+
+impl Output for OutputBus {
+    fn write(&mut self, frame: output::Frame) {
+        self.0.borrow_mut().push(frame);
+    }
+
+    fn set_parity(&mut self, _enabled: bool) {}
+}
+
+#[derive(Clone, Default)]
+pub struct Requests(Rc<RefCell<VecDeque<Request>>>);
+
+impl Spawn for Requests {
+    fn spawn(&mut self, request: Request) {
+        self.0.borrow_mut().push_back(request);
+    }
+}
+
+pub struct NoIndicators;
+
+impl Indicators for NoIndicators {
+    fn system_error_on(&mut self) {}
+    fn system_error_off(&mut self) {}
+    fn write_on(&mut self) {}
+    fn write_off(&mut self) {}
+    fn read_on(&mut self) {}
+    fn read_off(&mut self) {}
+}
+
+/// Adapter running against the SD card image.
+pub struct Harness {
+    device: Device<InputBus, OutputBus, Requests, NoIndicators>,
+    worker: Worker<Card<ImageDisk, TestClock>>,
+    input: InputBus,
+    output: OutputBus,
+    requests: Requests,
+}
+
+impl Harness {
+    pub fn new(image: Vec<u8>) -> Self {
+        Self::with_clock(image, TestClock::default())
+    }
+
+    pub fn with_clock(image: Vec<u8>, clock: TestClock) -> Self {
+        let input = InputBus::default();
+        let output = OutputBus::default();
+        let requests = Requests::default();
+        let buffers = [
+            Box::leak(Box::new([0; IO_BUFFER_SIZE])),
+            Box::leak(Box::new([0; IO_BUFFER_SIZE])),
+        ];
+        let device = Device::new(
+            input.clone(),
+            output.clone(),
+            requests.clone(),
+            NoIndicators,
+            buffers,
+        );
+
+        Self {
+            device,
+            worker: Worker::new(Card::new(disk(image), clock)),
+            input,
+            output,
+            requests,
+        }
+    }
+
+    pub fn configure(&mut self, config: Config) {
+        self.device.configure(config);
+    }
+
+    pub fn config(&self) -> Config {
+        self.device.config()
+    }
+
+    pub fn send(&mut self, action: input::Action) -> output::Frame {
+        self.input.0.borrow_mut().push_back(action);
+        self.device.run();
+        self.process();
+        *self
+            .output
+            .0
+            .borrow()
+            .last()
+            .expect("adapter did not reply")
+    }
+
+    pub fn command(&mut self, frame: input::Frame) -> output::Frame {
+        let payload = frame.payload();
+        self.send(input::Action::Data(payload, Parity::of(payload)))
+    }
+
+    /// Inserts or removes the card as reported by the detect pin.
+    pub fn detect_card(&mut self, attached: bool) {
+        self.device.set_attached(attached);
+        self.process();
+    }
+
+    pub fn set_time(&mut self, seconds: u32) {
+        assert_eq!(self.command(input::Frame::SetTime), output::Frame::Ack);
+        for word in [(seconds >> 16) as u16, seconds as u16] {
+            assert_eq!(self.command(input::Frame::Data(word)), output::Frame::Ack);
+        }
+    }
+
+    pub fn write_file(&mut self, address: u16, words: &[u16]) {
+        assert_eq!(
+            self.command(input::Frame::Address(address)),
+            output::Frame::Ack
+        );
+        assert_eq!(self.command(input::Frame::Write), output::Frame::Ack);
+        for word in words {
+            assert_eq!(self.command(input::Frame::Data(*word)), output::Frame::Ack);
+        }
+        assert_eq!(self.send(input::Action::Stop), output::Frame::Ack);
+    }
+
+    pub fn read_file(&mut self, address: u16, count: usize) -> Vec<u16> {
+        assert_eq!(
+            self.command(input::Frame::Address(address)),
+            output::Frame::Ack
+        );
+        assert_eq!(self.command(input::Frame::Read), output::Frame::Ack);
+        let words = (0..count)
+            .map(|_| match self.command(input::Frame::Data(0)) {
+                output::Frame::Data(word) => word,
+                frame => panic!("unexpected frame {frame:?}"),
+            })
+            .collect();
+        assert_eq!(self.send(input::Action::Stop), output::Frame::Ack);
+        words
+    }
+
+    /// Total and free space in KB answered to the Capacity command.
+    pub fn capacity(&mut self) -> (u32, u32) {
+        let mut words = vec![match self.command(input::Frame::Capacity) {
+            output::Frame::Data(word) => word,
+            frame => panic!("unexpected frame {frame:?}"),
+        }];
+        for _ in 0..3 {
+            match self.command(input::Frame::Data(0)) {
+                output::Frame::Data(word) => words.push(word),
+                frame => panic!("unexpected frame {frame:?}"),
+            }
+        }
+        let kilobytes = |high: u16, low: u16| (high as u32) << 16 | low as u32;
+        (kilobytes(words[0], words[1]), kilobytes(words[2], words[3]))
+    }
+
+    fn process(&mut self) {
+        loop {
+            let request = self.requests.0.borrow_mut().pop_front();
+            match request {
+                Some(request) => self.device.complete(self.worker.process(request)),
+                None => break,
+            }
+        }
+    }
+
+    pub fn image(self) -> Vec<u8> {
+        self.worker.free().free().as_bytes().to_vec()
+    }
+}
+
+pub fn words_to_bytes(words: &[u16]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
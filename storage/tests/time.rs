@@ -0,0 +1,64 @@
+use sm2m_storage::{
+    time::{parse_time, timestamp, DEFAULT_TIME},
+    Clock, ClockTimeSource,
+};
+
+#[derive(Clone)]
+struct Counter(u32, bool);
+
+impl Clock for Counter {
+    fn counter(&self) -> u32 {
+        self.0
+    }
+
+    fn is_set(&self) -> bool {
+        self.1
+    }
+
+    fn set(&mut self, seconds: u32) {
+        *self = Self(seconds, true);
+    }
+}
+
+#[test]
+fn time_is_parsed_from_text() {
+    assert_eq!(parse_time(b"1980-01-01 00:00:00"), Some(315_532_800));
+    assert_eq!(parse_time(b"2024-06-17 13:15:30\r\n"), Some(1_718_630_130));
+    assert_eq!(parse_time(b"2024-02-29T23:59:58"), Some(1_709_251_198));
+}
+
+#[test]
+fn invalid_time_is_rejected() {
+    for text in [
+        &b""[..],
+        b"2024-06-17",
+        b"2024-06-17 13:15",
+        b"17.06.2024 13:15:30",
+        b"2023-02-29 00:00:00",
+        b"2024-13-01 00:00:00",
+        b"2024-06-00 00:00:00",
+        b"2024-06-17 24:00:00",
+        b"1979-12-31 23:59:59",
+        b"2024-06-17 13:15:3x",
+    ] {
+        assert_eq!(parse_time(text), None);
+    }
+}
+
+#[test]
+fn timestamp_is_split_into_calendar_date() {
+    let time = timestamp(1_709_251_198);
+    assert_eq!(time.year_since_1970, 54);
+    assert_eq!(time.zero_indexed_month, 1);
+    assert_eq!(time.zero_indexed_day, 28);
+    assert_eq!((time.hours, time.minutes, time.seconds), (23, 59, 58));
+}
+
+#[test]
+fn time_counts_up_from_default_until_set() {
+    let mut source = ClockTimeSource(Counter(90, false));
+    assert_eq!(source.now(), DEFAULT_TIME + 90);
+
+    source.0.set(1_718_630_130);
+    assert_eq!(source.now(), 1_718_630_130);
+}